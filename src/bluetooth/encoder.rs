@@ -0,0 +1,244 @@
+/// RuuviTag data format 5 encoding for simulated tags and round-trip testing
+use crate::models::RuuviData;
+
+const DATA_FORMAT_5: u8 = 5; // RuuviTag data format version 5 (RAWv2)
+
+/// Encode structured data into a RuuviTag data format 5 payload
+///
+/// This is the inverse of the format 5 decoder: every value is scaled back to
+/// its raw integer representation, rounded to the nearest step of the format's
+/// resolution and clamped to the valid range. Missing values are encoded as
+/// their "not available" sentinel (0x8000 for signed, 0xFFFF for unsigned
+/// 16-bit values, 0xFF for the movement counter, all bits set for battery
+/// voltage, TX power and MAC address).
+///
+/// Decoding the encoded payload yields the original values within the format's
+/// resolution (0.005°C, 0.0025%, 1 Pa, 0.001 g, 1 mV, 2 dBm).
+///
+/// # Arguments
+/// * `data` - Sensor data to encode, including battery, TX power and sequence number
+/// * `mac` - MAC address to embed in bytes 18-23, or None if not available
+///
+/// # Returns
+/// The 24-byte manufacturer data payload (without the manufacturer ID)
+pub fn encode_ruuvi_data(data: &RuuviData, mac: Option<[u8; 6]>) -> [u8; 24] {
+    let mut payload = [0u8; 24];
+    payload[0] = DATA_FORMAT_5;
+
+    // Temperature: 0.005°C steps as signed 16-bit integer
+    let temperature = encode_i16(data.temperature, 0.005, 0.0);
+    payload[1..3].copy_from_slice(&temperature.to_be_bytes());
+
+    // Humidity: 0.0025% steps as unsigned 16-bit integer
+    let humidity = encode_u16(data.humidity, 0.0025, 0.0);
+    payload[3..5].copy_from_slice(&humidity.to_be_bytes());
+
+    // Pressure: hPa back to Pa with a -50000 Pa offset
+    let pressure = encode_u16(data.pressure, 0.01, 500.0);
+    payload[5..7].copy_from_slice(&pressure.to_be_bytes());
+
+    // Acceleration: 0.001 g steps as signed 16-bit integers
+    let acc_x = encode_i16(data.acceleration_x, 0.001, 0.0);
+    let acc_y = encode_i16(data.acceleration_y, 0.001, 0.0);
+    let acc_z = encode_i16(data.acceleration_z, 0.001, 0.0);
+    payload[7..9].copy_from_slice(&acc_x.to_be_bytes());
+    payload[9..11].copy_from_slice(&acc_y.to_be_bytes());
+    payload[11..13].copy_from_slice(&acc_z.to_be_bytes());
+
+    // Power info: 11 bits of battery voltage above 1.6 V in mV,
+    // 5 bits of TX power above -40 dBm in 2 dBm steps
+    let battery_bits = match data.battery_voltage {
+        Some(mv) => mv.clamp(1600, 1600 + 2046) - 1600,
+        None => 0x7FF,
+    };
+    let tx_power_bits = match data.tx_power {
+        Some(dbm) => ((dbm.clamp(-40, 20) as f32 + 40.0) / 2.0).round() as u16,
+        None => 0x1F,
+    };
+    let power_info = (battery_bits << 5) | tx_power_bits;
+    payload[13..15].copy_from_slice(&power_info.to_be_bytes());
+
+    // Movement counter: 0xFF is reserved for "not available"
+    payload[15] = data.movement_counter.map(|m| m.min(0xFE)).unwrap_or(0xFF);
+
+    // Measurement sequence number: 0xFFFF is reserved for "not available"
+    let sequence = data
+        .measurement_sequence
+        .map(|s| s.min(0xFFFE))
+        .unwrap_or(0xFFFF);
+    payload[16..18].copy_from_slice(&sequence.to_be_bytes());
+
+    // MAC address: all bits set means "not available"
+    payload[18..24].copy_from_slice(&mac.unwrap_or([0xFF; 6]));
+
+    payload
+}
+
+/// Scale a value to a signed 16-bit integer, using 0x8000 for missing values
+fn encode_i16(value: Option<f32>, resolution: f32, offset: f32) -> i16 {
+    match value {
+        Some(value) => ((value - offset) / resolution)
+            .round()
+            .clamp(-(i16::MAX as f32), i16::MAX as f32) as i16,
+        None => i16::MIN,
+    }
+}
+
+/// Scale a value to an unsigned 16-bit integer, using 0xFFFF for missing values
+fn encode_u16(value: Option<f32>, resolution: f32, offset: f32) -> u16 {
+    match value {
+        Some(value) => ((value - offset) / resolution)
+            .round()
+            .clamp(0.0, (u16::MAX - 1) as f32) as u16,
+        None => u16::MAX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::scanner::{decode_mac, decode_ruuvi_data};
+    use crate::utils::parse_hex;
+
+    const MAC: [u8; 6] = [0xCB, 0xB8, 0x33, 0x4C, 0x88, 0x4F];
+
+    fn sample() -> RuuviData {
+        RuuviData {
+            temperature: Some(24.3),
+            humidity: Some(53.49),
+            pressure: Some(1000.44),
+            acceleration_x: Some(0.004),
+            acceleration_y: Some(-0.004),
+            acceleration_z: Some(1.036),
+            movement_counter: Some(66),
+            battery_voltage: Some(2977),
+            tx_power: Some(4),
+            measurement_sequence: Some(205),
+            rssi: None,
+            adapter: None,
+            received_at: None,
+        }
+    }
+
+    fn round_trip(data: &RuuviData) -> RuuviData {
+        decode_ruuvi_data(&encode_ruuvi_data(data, Some(MAC)), None).unwrap()
+    }
+
+    fn assert_close(actual: Option<f32>, expected: f32, tolerance: f32) {
+        let actual = actual.expect("value should be present");
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn matches_published_format_5_vector() {
+        let payload = encode_ruuvi_data(&sample(), Some(MAC));
+        let expected = parse_hex("0512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F").unwrap();
+        assert_eq!(payload.to_vec(), expected);
+    }
+
+    #[test]
+    fn round_trips_value_ranges() {
+        for step in -32..=32 {
+            let step = step as f32 / 32.0;
+            let data = RuuviData {
+                temperature: Some(step * 163.8),
+                humidity: Some((step + 1.0) * 50.0),
+                pressure: Some(500.0 + (step + 1.0) * 327.0),
+                acceleration_x: Some(step * 32.7),
+                acceleration_y: Some(-step * 16.0),
+                acceleration_z: Some(step),
+                ..sample()
+            };
+            let decoded = round_trip(&data);
+
+            // Resolution of the format plus the rounding applied by the decoder
+            assert_close(decoded.temperature, data.temperature.unwrap(), 0.01);
+            assert_close(decoded.humidity, data.humidity.unwrap(), 0.01);
+            assert_close(decoded.pressure, data.pressure.unwrap(), 0.01);
+            assert_close(decoded.acceleration_x, data.acceleration_x.unwrap(), 0.001);
+            assert_close(decoded.acceleration_y, data.acceleration_y.unwrap(), 0.001);
+            assert_close(decoded.acceleration_z, data.acceleration_z.unwrap(), 0.001);
+        }
+
+        let decoded = round_trip(&sample());
+        assert_eq!(decoded.movement_counter, Some(66));
+        assert_eq!(decoded.battery_voltage, Some(2977));
+        assert_eq!(decoded.tx_power, Some(4));
+        assert_eq!(decoded.measurement_sequence, Some(205));
+    }
+
+    #[test]
+    fn round_trips_missing_values_as_sentinels() {
+        let data = RuuviData {
+            temperature: None,
+            humidity: None,
+            acceleration_y: None,
+            movement_counter: None,
+            battery_voltage: None,
+            tx_power: None,
+            measurement_sequence: None,
+            ..sample()
+        };
+        let payload = encode_ruuvi_data(&data, None);
+        let decoded = decode_ruuvi_data(&payload, None).unwrap();
+
+        assert_eq!(&payload[1..5], &[0x80, 0x00, 0xFF, 0xFF]);
+        assert_eq!(decoded.temperature, None);
+        assert_eq!(decoded.humidity, None);
+        assert_close(decoded.pressure, 1000.44, 0.01);
+        assert_eq!(decoded.acceleration_y, None);
+        assert_eq!(decoded.movement_counter, None);
+        assert_eq!(decoded.battery_voltage, None);
+        assert_eq!(decoded.tx_power, None);
+        assert_eq!(decoded.measurement_sequence, None);
+        assert_eq!(decode_mac(&payload), None);
+    }
+
+    #[test]
+    fn clamps_power_info_to_its_range() {
+        for (battery, expected) in [(1500, 1600), (1600, 1600), (3646, 3646), (3700, 3646)] {
+            let data = RuuviData {
+                battery_voltage: Some(battery),
+                ..sample()
+            };
+            assert_eq!(round_trip(&data).battery_voltage, Some(expected));
+        }
+
+        for (tx_power, expected) in [(-60, -40), (-40, -40), (20, 20), (30, 20), (5, 6)] {
+            let data = RuuviData {
+                tx_power: Some(tx_power),
+                ..sample()
+            };
+            assert_eq!(round_trip(&data).tx_power, Some(expected));
+        }
+    }
+
+    #[test]
+    fn clamps_without_producing_sentinels() {
+        let data = RuuviData {
+            temperature: Some(-500.0),
+            humidity: Some(200.0),
+            pressure: Some(2000.0),
+            acceleration_x: Some(-40.0),
+            acceleration_y: Some(40.0),
+            movement_counter: Some(255),
+            measurement_sequence: Some(u16::MAX),
+            ..sample()
+        };
+        let decoded = round_trip(&data);
+
+        // The lowest encodable value is 0x8001, 0x8000 would read as "not available"
+        assert_close(decoded.temperature, -163.835, 0.01);
+        assert_close(decoded.humidity, 100.0, 0.0);
+        assert_close(decoded.pressure, 1155.34, 0.01);
+        assert_close(decoded.acceleration_x, -32.767, 0.0);
+        assert_close(decoded.acceleration_y, 32.767, 0.0);
+        assert_eq!(decoded.movement_counter, Some(254));
+        assert_eq!(decoded.measurement_sequence, Some(65534));
+    }
+}
@@ -0,0 +1,90 @@
+/// Bluetooth adapter health tracking
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Health state of a single adapter's scanner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdapterHealth {
+    /// Scanner is acquiring the session and starting discovery
+    Starting,
+    /// Discovery is running
+    Healthy,
+    /// Scanner failed and is waiting to be restarted
+    Failing,
+    /// Adapter is being power-cycled after repeated failures
+    Recovering,
+}
+
+impl fmt::Display for AdapterHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AdapterHealth::Starting => "starting",
+            AdapterHealth::Healthy => "healthy",
+            AdapterHealth::Failing => "failing",
+            AdapterHealth::Recovering => "recovering",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Health state and failure counters of a single adapter's scanner
+#[derive(Debug, Clone)]
+pub struct AdapterStatus {
+    pub health: AdapterHealth,
+    /// Failures since the scanner last ran stably
+    pub consecutive_failures: u32,
+    /// Failures since the service started
+    pub total_failures: u32,
+    /// Power cycles since the service started
+    pub power_cycles: u32,
+}
+
+impl Default for AdapterStatus {
+    fn default() -> Self {
+        AdapterStatus {
+            health: AdapterHealth::Starting,
+            consecutive_failures: 0,
+            total_failures: 0,
+            power_cycles: 0,
+        }
+    }
+}
+
+/// Shared health status of all scanners
+///
+/// Key: adapter name, or "default" for the system default adapter
+pub type AdapterHealthMap = Arc<Mutex<HashMap<String, AdapterStatus>>>;
+
+/// Update the health status of an adapter, logging state transitions
+///
+/// # Arguments
+/// * `health` - Shared health status of all scanners
+/// * `adapter` - Adapter name as used as key in the map
+/// * `update` - Modification applied to the adapter's status
+pub fn update_health(
+    health: &AdapterHealthMap,
+    adapter: &str,
+    update: impl FnOnce(&mut AdapterStatus),
+) -> AdapterStatus {
+    let mut health = health.lock().unwrap_or_else(|e| e.into_inner());
+    let status = health.entry(adapter.to_string()).or_default();
+    let previous = status.health;
+    update(status);
+
+    if status.health != previous {
+        match status.health {
+            AdapterHealth::Healthy | AdapterHealth::Starting => info!(
+                "Bluetooth adapter {} is {} (was {})",
+                adapter, status.health, previous
+            ),
+            AdapterHealth::Failing | AdapterHealth::Recovering => warn!(
+                "Bluetooth adapter {} is {} (was {}, {} consecutive failures)",
+                adapter, status.health, previous, status.consecutive_failures
+            ),
+        }
+    }
+
+    status.clone()
+}
@@ -0,0 +1,385 @@
+/// Download of the on-tag history log over the Nordic UART service
+use futures_util::{Stream, StreamExt};
+use log::debug;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use time::OffsetDateTime;
+use tokio::time::{timeout, Duration};
+
+use crate::models::HistoryRecord;
+
+// Nordic UART service used by RuuviTag firmware 3.x for log access
+const NUS_SERVICE_UUID: bluer::Uuid =
+    bluer::Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+const NUS_RX_UUID: bluer::Uuid = bluer::Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e); // Write commands
+const NUS_TX_UUID: bluer::Uuid = bluer::Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e); // Notify responses
+
+// Log protocol constants
+const LOG_MESSAGE_LEN: usize = 11; // Length of every log command and response
+const LOG_ENDPOINT_ALL: u8 = 0x3A; // Addresses all sensors (temperature, humidity, pressure)
+const LOG_ENDPOINT_TEMPERATURE: u8 = 0x30; // Temperature in 0.01°C
+const LOG_ENDPOINT_HUMIDITY: u8 = 0x31; // Relative humidity in 0.01%
+const LOG_ENDPOINT_PRESSURE: u8 = 0x32; // Pressure in Pa
+const LOG_OP_VALUE: u8 = 0x10; // Response carrying one logged value
+const LOG_OP_READ: u8 = 0x11; // Command requesting logged values since a time
+const LOG_READ_TIMEOUT_SECS: u64 = 10; // Maximum wait for the next log response
+
+/// Errors that can occur while downloading the history log
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistoryError {
+    /// Connecting to the tag or accessing its GATT services failed
+    Connection(String),
+    /// The tag does not offer the Nordic UART service (firmware older than 3.x)
+    ServiceNotFound,
+    /// The tag stopped sending log responses before the end marker
+    Timeout,
+    /// The connection closed before the end marker was received
+    Disconnected,
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryError::Connection(e) => write!(f, "GATT connection failed: {}", e),
+            HistoryError::ServiceNotFound => write!(f, "Nordic UART service not found"),
+            HistoryError::Timeout => write!(f, "timed out waiting for log data"),
+            HistoryError::Disconnected => write!(f, "connection closed during log download"),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+impl From<bluer::Error> for HistoryError {
+    fn from(e: bluer::Error) -> Self {
+        HistoryError::Connection(e.to_string())
+    }
+}
+
+/// A GATT peer that can be connected to by MAC address
+///
+/// Implemented over BlueZ by `BluerGattPeer`, and by mocks that answer the
+/// log protocol without a Bluetooth adapter.
+pub trait GattPeer {
+    type Connection: NusConnection + Send;
+
+    /// Connect to the tag and subscribe to its Nordic UART responses
+    fn connect(
+        &self,
+        mac: &str,
+    ) -> impl Future<Output = Result<Self::Connection, HistoryError>> + Send;
+}
+
+/// An open Nordic UART connection to a tag
+pub trait NusConnection {
+    /// Write a command to the tag's RX characteristic
+    fn write(&mut self, data: &[u8]) -> impl Future<Output = Result<(), HistoryError>> + Send;
+
+    /// Wait for the next notification from the tag's TX characteristic
+    ///
+    /// # Returns
+    /// The notification, or None if the connection was closed
+    fn next_notification(&mut self) -> impl Future<Output = Option<Vec<u8>>> + Send;
+
+    /// Close the connection
+    fn disconnect(self) -> impl Future<Output = ()> + Send;
+}
+
+/// Download the history log entries logged since the given time
+///
+/// Sends a log read command for all sensors and collects the returned values
+/// until the end marker, merging temperature, humidity and pressure logged at
+/// the same time into one record.
+///
+/// # Arguments
+/// * `connection` - Open Nordic UART connection to the tag
+/// * `since` - Only entries logged after this time are requested
+/// * `now` - Current time, which the tag uses to convert its uptime-based log to real time
+///
+/// # Returns
+/// Records sorted by time, or error if the download did not complete
+pub async fn download_history<C: NusConnection>(
+    connection: &mut C,
+    since: OffsetDateTime,
+    now: OffsetDateTime,
+) -> Result<Vec<HistoryRecord>, HistoryError> {
+    let mut command = [0u8; LOG_MESSAGE_LEN];
+    command[0] = LOG_ENDPOINT_ALL;
+    command[1] = LOG_ENDPOINT_ALL;
+    command[2] = LOG_OP_READ;
+    command[3..7].copy_from_slice(&unix_time(now).to_be_bytes());
+    command[7..11].copy_from_slice(&unix_time(since).to_be_bytes());
+    connection.write(&command).await?;
+
+    let mut records: BTreeMap<u32, HistoryRecord> = BTreeMap::new();
+    loop {
+        let notification = timeout(
+            Duration::from_secs(LOG_READ_TIMEOUT_SECS),
+            connection.next_notification(),
+        )
+        .await
+        .map_err(|_| HistoryError::Timeout)?
+        .ok_or(HistoryError::Disconnected)?;
+
+        if notification.len() != LOG_MESSAGE_LEN || notification[2] != LOG_OP_VALUE {
+            debug!("Ignoring unexpected log response {:02x?}", notification);
+            continue;
+        }
+
+        // All time and value bytes set marks the end of the log
+        if notification[3..].iter().all(|&b| b == 0xFF) {
+            break;
+        }
+
+        let timestamp = u32::from_be_bytes([
+            notification[3],
+            notification[4],
+            notification[5],
+            notification[6],
+        ]);
+        let value = i32::from_be_bytes([
+            notification[7],
+            notification[8],
+            notification[9],
+            notification[10],
+        ]);
+        let Ok(time) = OffsetDateTime::from_unix_timestamp(timestamp as i64) else {
+            continue;
+        };
+
+        let record = records.entry(timestamp).or_insert(HistoryRecord {
+            time,
+            temperature: None,
+            humidity: None,
+            pressure: None,
+        });
+        match notification[0] {
+            LOG_ENDPOINT_TEMPERATURE => record.temperature = Some(value as f32 / 100.0),
+            LOG_ENDPOINT_HUMIDITY => record.humidity = Some(value as f32 / 100.0),
+            LOG_ENDPOINT_PRESSURE => record.pressure = Some(value as f32 / 100.0),
+            _ => {}
+        }
+    }
+
+    Ok(records.into_values().collect())
+}
+
+/// Convert a time to the 32-bit Unix time used by the log protocol
+fn unix_time(time: OffsetDateTime) -> u32 {
+    time.unix_timestamp().clamp(0, u32::MAX as i64) as u32
+}
+
+/// GATT peer access over BlueZ
+pub struct BluerGattPeer {
+    adapter_name: Option<String>,
+}
+
+impl BluerGattPeer {
+    /// Create a peer connector using the given adapter
+    ///
+    /// # Arguments
+    /// * `adapter_name` - Name of the Bluetooth adapter to connect with, or None for the default adapter
+    pub fn new(adapter_name: Option<String>) -> Self {
+        BluerGattPeer { adapter_name }
+    }
+}
+
+impl GattPeer for BluerGattPeer {
+    type Connection = BluerNusConnection;
+
+    async fn connect(&self, mac: &str) -> Result<BluerNusConnection, HistoryError> {
+        let session = bluer::Session::new().await?;
+        let adapter = match &self.adapter_name {
+            Some(name) => session.adapter(name)?,
+            None => session.default_adapter().await?,
+        };
+        let address: bluer::Address = mac
+            .parse()
+            .map_err(|_| HistoryError::Connection(format!("invalid MAC address {}", mac)))?;
+        let device = adapter.device(address)?;
+        if !device.is_connected().await? {
+            device.connect().await?;
+        }
+
+        // Find the RX and TX characteristics of the Nordic UART service
+        let mut rx = None;
+        let mut tx = None;
+        for service in device.services().await? {
+            if service.uuid().await? != NUS_SERVICE_UUID {
+                continue;
+            }
+            for characteristic in service.characteristics().await? {
+                match characteristic.uuid().await? {
+                    NUS_RX_UUID => rx = Some(characteristic),
+                    NUS_TX_UUID => tx = Some(characteristic),
+                    _ => {}
+                }
+            }
+        }
+        let (Some(rx), Some(tx)) = (rx, tx) else {
+            let _ = device.disconnect().await;
+            return Err(HistoryError::ServiceNotFound);
+        };
+
+        let notifications = tx.notify().await?;
+        Ok(BluerNusConnection {
+            device,
+            rx,
+            notifications: Box::pin(notifications),
+        })
+    }
+}
+
+/// Nordic UART connection over BlueZ
+pub struct BluerNusConnection {
+    device: bluer::Device,
+    rx: bluer::gatt::remote::Characteristic,
+    notifications: Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>,
+}
+
+impl NusConnection for BluerNusConnection {
+    async fn write(&mut self, data: &[u8]) -> Result<(), HistoryError> {
+        self.rx.write(data).await?;
+        Ok(())
+    }
+
+    async fn next_notification(&mut self) -> Option<Vec<u8>> {
+        self.notifications.next().await
+    }
+
+    async fn disconnect(self) {
+        if let Err(e) = self.device.disconnect().await {
+            debug!("Failed to disconnect from {}: {}", self.device.address(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Connection replaying scripted notifications
+    ///
+    /// Once the script is exhausted it either reports the connection as
+    /// closed or stays silent, depending on `hang_up`.
+    struct MockConnection {
+        notifications: VecDeque<Vec<u8>>,
+        written: Vec<Vec<u8>>,
+        hang_up: bool,
+    }
+
+    impl MockConnection {
+        fn new(notifications: Vec<Vec<u8>>, hang_up: bool) -> Self {
+            MockConnection {
+                notifications: notifications.into(),
+                written: Vec::new(),
+                hang_up,
+            }
+        }
+    }
+
+    impl NusConnection for MockConnection {
+        async fn write(&mut self, data: &[u8]) -> Result<(), HistoryError> {
+            self.written.push(data.to_vec());
+            Ok(())
+        }
+
+        async fn next_notification(&mut self) -> Option<Vec<u8>> {
+            match self.notifications.pop_front() {
+                Some(notification) => Some(notification),
+                None if self.hang_up => None,
+                None => std::future::pending().await,
+            }
+        }
+
+        async fn disconnect(self) {}
+    }
+
+    fn value(endpoint: u8, timestamp: u32, value: i32) -> Vec<u8> {
+        let mut message = vec![endpoint, endpoint, LOG_OP_VALUE];
+        message.extend_from_slice(&timestamp.to_be_bytes());
+        message.extend_from_slice(&value.to_be_bytes());
+        message
+    }
+
+    fn end_marker() -> Vec<u8> {
+        let mut message = vec![LOG_ENDPOINT_ALL, LOG_ENDPOINT_ALL, LOG_OP_VALUE];
+        message.extend_from_slice(&[0xFF; 8]);
+        message
+    }
+
+    fn time(timestamp: u32) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(timestamp as i64).unwrap()
+    }
+
+    #[tokio::test]
+    async fn merges_endpoints_until_end_marker() {
+        let mut connection = MockConnection::new(
+            vec![
+                value(LOG_ENDPOINT_TEMPERATURE, 1300, 2150),
+                value(LOG_ENDPOINT_HUMIDITY, 1300, 4520),
+                value(LOG_ENDPOINT_PRESSURE, 1300, 100210),
+                // Entries arrive grouped per sensor, not in time order
+                value(LOG_ENDPOINT_TEMPERATURE, 1000, -520),
+                vec![LOG_ENDPOINT_ALL, LOG_ENDPOINT_ALL, 0x20],
+                value(LOG_ENDPOINT_PRESSURE, 1000, 99870),
+                end_marker(),
+                value(LOG_ENDPOINT_TEMPERATURE, 1600, 2000),
+            ],
+            true,
+        );
+
+        let records = download_history(&mut connection, time(900), time(2000))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            connection.written,
+            vec![vec![
+                0x3A, 0x3A, 0x11, 0x00, 0x00, 0x07, 0xD0, 0x00, 0x00, 0x03, 0x84
+            ]]
+        );
+        assert_eq!(
+            records,
+            vec![
+                HistoryRecord {
+                    time: time(1000),
+                    temperature: Some(-5.2),
+                    humidity: None,
+                    pressure: Some(998.7),
+                },
+                HistoryRecord {
+                    time: time(1300),
+                    temperature: Some(21.5),
+                    humidity: Some(45.2),
+                    pressure: Some(1002.1),
+                },
+            ]
+        );
+        // Nothing after the end marker is read
+        assert_eq!(connection.notifications.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_without_end_marker() {
+        let mut connection =
+            MockConnection::new(vec![value(LOG_ENDPOINT_TEMPERATURE, 1000, 2150)], false);
+
+        let result = download_history(&mut connection, time(900), time(2000)).await;
+
+        assert_eq!(result, Err(HistoryError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn fails_when_connection_closes_early() {
+        let mut connection =
+            MockConnection::new(vec![value(LOG_ENDPOINT_TEMPERATURE, 1000, 2150)], true);
+
+        let result = download_history(&mut connection, time(900), time(2000)).await;
+
+        assert_eq!(result, Err(HistoryError::Disconnected));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod encoder;
+pub mod health;
+pub mod history;
 pub mod scanner;
 
-pub use scanner::scan_for_ruuvitags;
+pub use scanner::spawn_scanners;
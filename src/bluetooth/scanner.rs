@@ -1,16 +1,237 @@
 /// Bluetooth Low Energy scanning and RuuviTag data decoding
+use bluer::{AdapterEvent, DeviceEvent, DeviceProperty};
 use futures_util::StreamExt;
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
+use openssl::symm::{Cipher, Crypter, Mode};
 use std::collections::HashMap;
-use tokio::time::{sleep, Duration};
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
 
-use crate::config::SensorConfig;
-use crate::models::RuuviData;
+use super::health::{update_health, AdapterHealth, AdapterHealthMap};
+use crate::models::{Advertisement, AirQualityData, RuuviData, SensorReading};
 
 // RuuviTag protocol constants
 const RUUVITAG_MANUFACTURER_ID: u16 = 0x0499; // Ruuvi Innovations Ltd. manufacturer ID
-const DATA_FORMAT: u8 = 5; // RuuviTag data format version 5
-const SCAN_DURATION_SECS: u64 = 20; // How long to actively scan for devices
+const DATA_FORMAT_3: u8 = 3; // RuuviTag data format version 3 (RAWv1)
+const DATA_FORMAT_5: u8 = 5; // RuuviTag data format version 5 (RAWv2)
+const DATA_FORMAT_6: u8 = 6; // Ruuvi Air data format version 6
+const DATA_FORMAT_8: u8 = 8; // RuuviTag encrypted data format version 8
+const DATA_FORMAT_E1: u8 = 0xE1; // Ruuvi Air extended data format E1
+const DATA_FORMAT_3_LEN: usize = 14; // Payload length of data format 3
+const DATA_FORMAT_5_LEN: usize = 24; // Payload length of data format 5
+const DATA_FORMAT_6_LEN: usize = 20; // Payload length of data format 6
+const DATA_FORMAT_8_LEN: usize = 24; // Payload length of data format 8
+const DATA_FORMAT_E1_LEN: usize = 40; // Payload length of data format E1
+
+// Scanner recovery constants
+const DEFAULT_ADAPTER_LABEL: &str = "default"; // Health map key for the system default adapter
+const SCANNER_BACKOFF_INITIAL_SECS: u64 = 2; // Delay before the first restart of a failed scanner
+const SCANNER_BACKOFF_MAX_SECS: u64 = 300; // Upper limit for the exponential restart delay
+const SCANNER_STABLE_RUN_SECS: u64 = 300; // A run this long resets the consecutive failure count
+const POWER_CYCLE_AFTER_FAILURES: u32 = 3; // Power-cycle the adapter after this many consecutive failures
+const POWER_CYCLE_OFF_SECS: u64 = 2; // How long the adapter is kept powered off
+
+// "Not available" sentinel values used by data formats 5, 6, 8 and E1
+const INVALID_I16: i16 = i16::MIN; // 0x8000 for temperature and acceleration
+const INVALID_U16: u16 = u16::MAX; // 0xFFFF for humidity, pressure, particulate matter and CO2
+const INVALID_U8: u8 = u8::MAX; // 0xFF for movement counter and format 6 luminosity
+const INVALID_BATTERY: u16 = 0x7FF; // All 11 battery voltage bits set
+const INVALID_TX_POWER: u16 = 0x1F; // All 5 TX power bits set
+const INVALID_INDEX: u16 = 0x1FF; // All 9 VOC or NOx index bits set
+const INVALID_U24: u32 = 0xFF_FFFF; // All 24 bits set for E1 luminosity and sequence number
+
+/// Reasons a Ruuvi manufacturer data payload could not be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The payload contained no bytes at all
+    Empty,
+    /// The payload length does not match its data format
+    InvalidLength { format: u8, length: usize },
+    /// The data format byte is not one this decoder supports
+    UnsupportedFormat(u8),
+    /// Encrypted data (format 8) was received but no key is configured for the tag
+    MissingKey,
+    /// Decrypting an encrypted payload failed
+    Decryption(String),
+    /// The CRC of a decrypted payload did not match, the key is probably wrong
+    CrcMismatch,
+    /// Every measurement in the payload held its "not available" sentinel value
+    NoValidMeasurements,
+}
+
+impl DecodeError {
+    /// Short, stable name of the error class for counting and logging
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DecodeError::Empty => "empty",
+            DecodeError::InvalidLength { .. } => "invalid_length",
+            DecodeError::UnsupportedFormat(_) => "unsupported_format",
+            DecodeError::MissingKey => "missing_key",
+            DecodeError::Decryption(_) => "decryption_failed",
+            DecodeError::CrcMismatch => "crc_mismatch",
+            DecodeError::NoValidMeasurements => "no_valid_measurements",
+        }
+    }
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "empty payload"),
+            DecodeError::InvalidLength { format, length } => {
+                write!(f, "invalid length {} for data format {}", length, format)
+            }
+            DecodeError::UnsupportedFormat(format) => {
+                write!(f, "unsupported data format {}", format)
+            }
+            DecodeError::MissingKey => write!(
+                f,
+                "encrypted data (format 8) received but no decryption key is configured"
+            ),
+            DecodeError::Decryption(e) => write!(f, "decryption failed: {}", e),
+            DecodeError::CrcMismatch => write!(
+                f,
+                "CRC check failed after decryption, the configured key is probably wrong"
+            ),
+            DecodeError::NoValidMeasurements => {
+                write!(f, "payload contains no valid measurements")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decode any supported Ruuvi manufacturer data payload
+///
+/// Ruuvi Air formats (6 and E1) are decoded into air quality readings,
+/// everything else is handed to the RuuviTag decoder.
+///
+/// # Arguments
+/// * `data` - Raw manufacturer data bytes from BLE advertisement
+/// * `key` - AES-128 key of the sending tag, required for encrypted data format 8
+///
+/// # Returns
+/// Ok(SensorReading) if decoding succeeds, DecodeError describing the failure otherwise
+pub fn decode_advertisement(
+    data: &[u8],
+    key: Option<&[u8; 16]>,
+) -> Result<SensorReading, DecodeError> {
+    match data.first() {
+        Some(&DATA_FORMAT_6) | Some(&DATA_FORMAT_E1) => {
+            decode_air_quality_data(data).map(SensorReading::AirQuality)
+        }
+        _ => decode_ruuvi_data(data, key).map(SensorReading::Tag),
+    }
+}
+
+/// Extract the Ruuvi manufacturer data from a raw BLE advertisement
+///
+/// Walks the advertising data structures (length, type, data) and returns the
+/// payload of the manufacturer specific data structure (type 0xFF) carrying
+/// the Ruuvi manufacturer ID, without the ID itself. This is the form in which
+/// gateways forward advertisements.
+///
+/// # Arguments
+/// * `raw` - Complete advertising data as received over the air
+///
+/// # Returns
+/// The manufacturer data payload, or None if the advertisement is not from a Ruuvi device
+pub fn extract_ruuvi_manufacturer_data(raw: &[u8]) -> Option<&[u8]> {
+    let mut rest = raw;
+    while let Some((&length, tail)) = rest.split_first() {
+        let length = length as usize;
+        if length == 0 || length > tail.len() {
+            return None;
+        }
+        let (structure, next) = tail.split_at(length);
+
+        // Manufacturer specific data: type 0xFF followed by the little-endian company ID
+        if structure[0] == 0xFF && structure.len() >= 3 {
+            let company_id = u16::from_le_bytes([structure[1], structure[2]]);
+            if company_id == RUUVITAG_MANUFACTURER_ID {
+                return Some(&structure[3..]);
+            }
+        }
+        rest = next;
+    }
+    None
+}
+
+/// Decode the sensor MAC address embedded in a Ruuvi manufacturer data payload
+///
+/// Data formats 5 and 8 carry the full MAC address in bytes 18-23 and format E1
+/// in bytes 34-39. Formats 3 and 6 do not carry a full MAC address.
+///
+/// # Arguments
+/// * `data` - Raw manufacturer data bytes from BLE advertisement
+///
+/// # Returns
+/// Some(MAC) formatted as uppercase colon-separated hex, None if not available
+pub fn decode_mac(data: &[u8]) -> Option<String> {
+    let mac = match (data.first(), data.len()) {
+        (Some(&DATA_FORMAT_5), DATA_FORMAT_5_LEN) | (Some(&DATA_FORMAT_8), DATA_FORMAT_8_LEN) => {
+            &data[18..24]
+        }
+        (Some(&DATA_FORMAT_E1), DATA_FORMAT_E1_LEN) => &data[34..40],
+        _ => return None,
+    };
+
+    // All bits set means the MAC address is not available
+    if mac.iter().all(|&b| b == 0xFF) {
+        return None;
+    }
+
+    Some(
+        mac.iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(":"),
+    )
+}
+
+/// Decode RuuviTag manufacturer data into structured data
+///
+/// Dispatches on the data format byte (byte 0) so that tags running older
+/// firmware (format 3), current firmware (format 5) and encrypted firmware
+/// (format 8) can be mixed.
+///
+/// # Arguments
+/// * `data` - Raw manufacturer data bytes from BLE advertisement
+/// * `key` - AES-128 key of the sending tag, required for encrypted data format 8
+///
+/// # Returns
+/// Ok(RuuviData) if decoding succeeds, DecodeError describing the failure otherwise
+pub fn decode_ruuvi_data(data: &[u8], key: Option<&[u8; 16]>) -> Result<RuuviData, DecodeError> {
+    let sensor_data = match (data.first(), data.len()) {
+        (Some(&DATA_FORMAT_5), DATA_FORMAT_5_LEN) => decode_format_5(data),
+        (Some(&DATA_FORMAT_3), DATA_FORMAT_3_LEN) => decode_format_3(data),
+        (Some(&DATA_FORMAT_8), DATA_FORMAT_8_LEN) => {
+            decode_format_8(data, key.ok_or(DecodeError::MissingKey)?)?
+        }
+        (Some(&format @ (DATA_FORMAT_3 | DATA_FORMAT_5 | DATA_FORMAT_8)), length) => {
+            return Err(DecodeError::InvalidLength { format, length })
+        }
+        (Some(&format), _) => return Err(DecodeError::UnsupportedFormat(format)),
+        (None, _) => return Err(DecodeError::Empty),
+    };
+
+    // A payload where every sensor reported "not available" carries no information
+    let has_measurement = sensor_data.temperature.is_some()
+        || sensor_data.humidity.is_some()
+        || sensor_data.pressure.is_some()
+        || sensor_data.acceleration_x.is_some()
+        || sensor_data.acceleration_y.is_some()
+        || sensor_data.acceleration_z.is_some()
+        || sensor_data.movement_counter.is_some();
+    if !has_measurement {
+        return Err(DecodeError::NoValidMeasurements);
+    }
+
+    Ok(sensor_data)
+}
 
 /// Decode RuuviTag manufacturer data format 5 into structured data
 ///
@@ -22,82 +243,422 @@ const SCAN_DURATION_SECS: u64 = 20; // How long to actively scan for devices
 /// - Bytes 7-8: Acceleration X (signed 16-bit, 0.001 g resolution)
 /// - Bytes 9-10: Acceleration Y (signed 16-bit, 0.001 g resolution)
 /// - Bytes 11-12: Acceleration Z (signed 16-bit, 0.001 g resolution)
-/// - Bytes 13-14: Battery voltage + TX power (not used here)
+/// - Bytes 13-14: Battery voltage (11 bits, +1600 mV offset) + TX power (5 bits, 2 dBm steps from -40 dBm)
 /// - Byte 15: Movement counter
-/// - Bytes 16-17: Measurement sequence number (not used here)
-/// - Bytes 18-23: MAC address (not used here, we get it from BLE)
+/// - Bytes 16-17: Measurement sequence number
+/// - Bytes 18-23: MAC address (see decode_mac)
+///
+/// Fields holding their "not available" sentinel value (0x8000 for signed,
+/// 0xFFFF for unsigned 16-bit values and the sequence number, 0xFF for the
+/// movement counter) are left empty.
+fn decode_format_5(data: &[u8]) -> RuuviData {
+    // Decode temperature: signed 16-bit integer * 0.005°C
+    let temperature = read_i16(data[1], data[2]).map(|t| t as f32 * 0.005);
+
+    // Decode humidity: unsigned 16-bit integer * 0.0025%, capped at 100%
+    let humidity = read_u16(data[3], data[4]).map(|h| (h as f32 * 0.0025).min(100.0));
+
+    // Decode pressure: unsigned 16-bit integer + 50000 Pa, convert to hPa
+    let pressure = read_u16(data[5], data[6]).map(|p| (p as f32 + 50000.0) / 100.0);
+
+    // Decode acceleration values: signed 16-bit integers * 0.001 g
+    let acc_x = read_i16(data[7], data[8]).map(|a| a as f32 * 0.001);
+    let acc_y = read_i16(data[9], data[10]).map(|a| a as f32 * 0.001);
+    let acc_z = read_i16(data[11], data[12]).map(|a| a as f32 * 0.001);
+
+    // Power info: first 11 bits are battery voltage above 1.6 V in mV,
+    // last 5 bits are TX power above -40 dBm in 2 dBm steps
+    let (battery_voltage, tx_power) = decode_power_info(u16::from_be_bytes([data[13], data[14]]));
+
+    // Movement counter: increments when significant movement is detected (sensor flips)
+    let movement_counter = Some(data[15]).filter(|&m| m != INVALID_U8);
+
+    // Measurement sequence number: increments with every new measurement
+    let measurement_sequence = read_u16(data[16], data[17]);
+
+    // Create RuuviData with proper rounding for display
+    RuuviData {
+        temperature: temperature.map(|t| (t * 100.0).round() / 100.0),
+        humidity: humidity.map(|h| (h * 100.0).round() / 100.0),
+        pressure: pressure.map(|p| (p * 100.0).round() / 100.0),
+        acceleration_x: acc_x.map(|a| (a * 1000.0).round() / 1000.0),
+        acceleration_y: acc_y.map(|a| (a * 1000.0).round() / 1000.0),
+        acceleration_z: acc_z.map(|a| (a * 1000.0).round() / 1000.0),
+        movement_counter,
+        battery_voltage,
+        tx_power,
+        measurement_sequence,
+        rssi: None,
+        adapter: None,
+        received_at: None,
+    }
+}
+
+/// Read a signed 16-bit big-endian value, treating 0x8000 as "not available"
+fn read_i16(high: u8, low: u8) -> Option<i16> {
+    Some(i16::from_be_bytes([high, low])).filter(|&v| v != INVALID_I16)
+}
+
+/// Read an unsigned 16-bit big-endian value, treating 0xFFFF as "not available"
+fn read_u16(high: u8, low: u8) -> Option<u16> {
+    Some(u16::from_be_bytes([high, low])).filter(|&v| v != INVALID_U16)
+}
+
+/// Split power info into battery voltage (mV) and TX power (dBm)
+///
+/// All bits set in either field means "not available".
+fn decode_power_info(power_info: u16) -> (Option<u16>, Option<i8>) {
+    let battery_bits = power_info >> 5;
+    let tx_power_bits = power_info & 0x1F;
+
+    let battery_voltage = Some(battery_bits)
+        .filter(|&b| b != INVALID_BATTERY)
+        .map(|b| b + 1600);
+    let tx_power = Some(tx_power_bits)
+        .filter(|&t| t != INVALID_TX_POWER)
+        .map(|t| t as i8 * 2 - 40);
+
+    (battery_voltage, tx_power)
+}
+
+/// Decode RuuviTag manufacturer data format 3 (RAWv1) into structured data
+///
+/// RuuviTag data format 3 uses a 14-byte payload with the following structure:
+/// - Byte 0: Data format (3)
+/// - Byte 1: Humidity (unsigned 8-bit, 0.5% resolution)
+/// - Byte 2: Temperature integer part (MSB is the sign bit)
+/// - Byte 3: Temperature fraction (0.01°C resolution)
+/// - Bytes 4-5: Pressure (unsigned 16-bit, +50000 Pa offset, 1 Pa resolution)
+/// - Bytes 6-7: Acceleration X (signed 16-bit, 0.001 g resolution)
+/// - Bytes 8-9: Acceleration Y (signed 16-bit, 0.001 g resolution)
+/// - Bytes 10-11: Acceleration Z (signed 16-bit, 0.001 g resolution)
+/// - Bytes 12-13: Battery voltage (unsigned 16-bit, mV)
+///
+/// Format 3 carries no movement counter, TX power or measurement sequence number,
+/// so those are left empty.
+fn decode_format_3(data: &[u8]) -> RuuviData {
+    // Decode humidity: unsigned 8-bit integer * 0.5%, capped at 100%
+    let humidity = (data[1] as f32 * 0.5).min(100.0);
+
+    // Decode temperature: sign-and-magnitude integer part plus hundredths
+    let magnitude = (data[2] & 0x7F) as f32 + data[3] as f32 / 100.0;
+    let temperature = if data[2] & 0x80 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    };
+
+    // Decode pressure: unsigned 16-bit integer + 50000 Pa, convert to hPa
+    let pressure = (u16::from_be_bytes([data[4], data[5]]) as f32 + 50000.0) / 100.0;
+
+    // Decode acceleration values: signed 16-bit integers * 0.001 g
+    let acc_x = i16::from_be_bytes([data[6], data[7]]) as f32 * 0.001;
+    let acc_y = i16::from_be_bytes([data[8], data[9]]) as f32 * 0.001;
+    let acc_z = i16::from_be_bytes([data[10], data[11]]) as f32 * 0.001;
+
+    // Battery voltage is reported directly in millivolts
+    let battery_voltage = u16::from_be_bytes([data[12], data[13]]);
+
+    RuuviData {
+        temperature: Some((temperature * 100.0).round() / 100.0),
+        humidity: Some(humidity),
+        pressure: Some((pressure * 100.0).round() / 100.0),
+        acceleration_x: Some((acc_x * 1000.0).round() / 1000.0),
+        acceleration_y: Some((acc_y * 1000.0).round() / 1000.0),
+        acceleration_z: Some((acc_z * 1000.0).round() / 1000.0),
+        movement_counter: None,
+        battery_voltage: Some(battery_voltage),
+        tx_power: None,
+        measurement_sequence: None,
+        rssi: None,
+        adapter: None,
+        received_at: None,
+    }
+}
+
+/// Decode RuuviTag manufacturer data format 8 (encrypted) into structured data
+///
+/// RuuviTag data format 8 uses a 24-byte payload with the following structure:
+/// - Byte 0: Data format (8)
+/// - Bytes 1-16: AES-128-ECB encrypted measurement block
+/// - Byte 17: CRC8 of the decrypted measurement block
+/// - Bytes 18-23: MAC address (see decode_mac)
+///
+/// The decrypted measurement block contains:
+/// - Bytes 0-1: Temperature (signed 16-bit, 0.005°C resolution)
+/// - Bytes 2-3: Humidity (unsigned 16-bit, 0.0025% resolution)
+/// - Bytes 4-5: Pressure (unsigned 16-bit, +50000 Pa offset, 1 Pa resolution)
+/// - Bytes 6-7: Battery voltage (11 bits, +1600 mV offset) + TX power (5 bits, 2 dBm steps from -40 dBm)
+/// - Byte 8: Movement counter
+/// - Bytes 9-10: Measurement sequence number
+/// - Bytes 11-15: Reserved
+///
+/// Format 8 carries no acceleration data, so those values are left empty.
+/// A CRC mismatch after decryption means the configured key is wrong.
+fn decode_format_8(data: &[u8], key: &[u8; 16]) -> Result<RuuviData, DecodeError> {
+    let block =
+        decrypt_block(&data[1..17], key).map_err(|e| DecodeError::Decryption(e.to_string()))?;
+
+    // Verify the checksum to detect a wrong decryption key
+    if crc8(&block) != data[17] {
+        return Err(DecodeError::CrcMismatch);
+    }
+
+    // Decode temperature, humidity and pressure exactly as in format 5
+    let temperature = read_i16(block[0], block[1]).map(|t| t as f32 * 0.005);
+    let humidity = read_u16(block[2], block[3]).map(|h| (h as f32 * 0.0025).min(100.0));
+    let pressure = read_u16(block[4], block[5]).map(|p| (p as f32 + 50000.0) / 100.0);
+
+    // Power info uses the same bit layout as format 5
+    let (battery_voltage, tx_power) = decode_power_info(u16::from_be_bytes([block[6], block[7]]));
+
+    Ok(RuuviData {
+        temperature: temperature.map(|t| (t * 100.0).round() / 100.0),
+        humidity: humidity.map(|h| (h * 100.0).round() / 100.0),
+        pressure: pressure.map(|p| (p * 100.0).round() / 100.0),
+        acceleration_x: None,
+        acceleration_y: None,
+        acceleration_z: None,
+        movement_counter: Some(block[8]).filter(|&m| m != INVALID_U8),
+        battery_voltage,
+        tx_power,
+        measurement_sequence: read_u16(block[9], block[10]),
+        rssi: None,
+        adapter: None,
+        received_at: None,
+    })
+}
+
+/// Decrypt a single 16-byte AES-128-ECB block
+fn decrypt_block(encrypted: &[u8], key: &[u8; 16]) -> Result<[u8; 16], openssl::error::ErrorStack> {
+    let mut crypter = Crypter::new(Cipher::aes_128_ecb(), Mode::Decrypt, key, None)?;
+    crypter.pad(false);
+
+    // OpenSSL requires room for one extra block in the output buffer
+    let mut output = [0u8; 32];
+    let mut count = crypter.update(encrypted, &mut output)?;
+    count += crypter.finalize(&mut output[count..])?;
+
+    let mut block = [0u8; 16];
+    block.copy_from_slice(&output[..count.min(16)]);
+    Ok(block)
+}
+
+/// Calculate CRC-8 (polynomial 0x07, initial value 0x00) over the given bytes
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Decode Ruuvi Air manufacturer data into structured air quality data
+///
+/// Dispatches on the data format byte (byte 0) between the BLE 4 compatible
+/// format 6 and the extended advertising format E1.
 ///
 /// # Arguments
 /// * `data` - Raw manufacturer data bytes from BLE advertisement
 ///
 /// # Returns
-/// Some(RuuviData) if decoding succeeds, None if data is invalid
-pub fn decode_ruuvi_data(data: &[u8]) -> Option<RuuviData> {
-    if data.len() != 24 || data[0] != DATA_FORMAT {
-        if !data.is_empty() {
-            warn!(
-                "Invalid RuuviTag data: len={}, format={}",
-                data.len(),
-                data[0]
-            );
+/// Ok(AirQualityData) if decoding succeeds, DecodeError describing the failure otherwise
+pub fn decode_air_quality_data(data: &[u8]) -> Result<AirQualityData, DecodeError> {
+    let air_data = match (data.first(), data.len()) {
+        (Some(&DATA_FORMAT_6), DATA_FORMAT_6_LEN) => decode_format_6(data),
+        (Some(&DATA_FORMAT_E1), DATA_FORMAT_E1_LEN) => decode_format_e1(data),
+        (Some(&format @ (DATA_FORMAT_6 | DATA_FORMAT_E1)), length) => {
+            return Err(DecodeError::InvalidLength { format, length })
         }
-        return None;
+        (Some(&format), _) => return Err(DecodeError::UnsupportedFormat(format)),
+        (None, _) => return Err(DecodeError::Empty),
+    };
+
+    // A payload where every sensor reported "not available" carries no information
+    let has_measurement = air_data.temperature.is_some()
+        || air_data.humidity.is_some()
+        || air_data.pressure.is_some()
+        || air_data.pm1_0.is_some()
+        || air_data.pm2_5.is_some()
+        || air_data.pm4_0.is_some()
+        || air_data.pm10_0.is_some()
+        || air_data.co2.is_some()
+        || air_data.voc_index.is_some()
+        || air_data.nox_index.is_some()
+        || air_data.luminosity.is_some();
+    if !has_measurement {
+        return Err(DecodeError::NoValidMeasurements);
     }
 
-    // Use a closure with error handling for clean code
-    match (|| -> Result<RuuviData, Box<dyn std::error::Error>> {
-        // Decode temperature: signed 16-bit integer * 0.005°C
-        let temperature = i16::from_be_bytes([data[1], data[2]]) as f32 * 0.005;
+    Ok(air_data)
+}
 
-        // Decode humidity: unsigned 16-bit integer * 0.0025%, capped at 100%
-        let humidity = (u16::from_be_bytes([data[3], data[4]]) as f32 * 0.0025).min(100.0);
+/// Decode Ruuvi Air manufacturer data format 6 into structured data
+///
+/// Ruuvi Air data format 6 uses a 20-byte payload with the following structure:
+/// - Byte 0: Data format (6)
+/// - Bytes 1-2: Temperature (signed 16-bit, 0.005°C resolution)
+/// - Bytes 3-4: Humidity (unsigned 16-bit, 0.0025% resolution)
+/// - Bytes 5-6: Pressure (unsigned 16-bit, +50000 Pa offset, 1 Pa resolution)
+/// - Bytes 7-8: PM2.5 (unsigned 16-bit, 0.1 µg/m³ resolution)
+/// - Bytes 9-10: CO2 (unsigned 16-bit, ppm)
+/// - Byte 11: VOC index, bits 8-1 (bit 0 in flags)
+/// - Byte 12: NOx index, bits 8-1 (bit 0 in flags)
+/// - Byte 13: Luminosity (logarithmic 8-bit)
+/// - Byte 14: Reserved
+/// - Byte 15: Measurement sequence number, lowest 8 bits
+/// - Byte 16: Flags (bit 6: VOC index bit 0, bit 7: NOx index bit 0)
+/// - Bytes 17-19: Lowest 3 bytes of MAC address (not used here, we get it from BLE)
+///
+/// Format 6 carries no PM1.0, PM4.0 or PM10 readings, so those are left empty.
+/// Fields holding their "not available" sentinel value (0x8000 for temperature,
+/// 0xFFFF for the other 16-bit values, 0x1FF for the VOC and NOx indices and
+/// 0xFF for luminosity) are left empty as well.
+fn decode_format_6(data: &[u8]) -> AirQualityData {
+    // Decode temperature, humidity and pressure exactly as in format 5
+    let temperature = read_i16(data[1], data[2]).map(|t| t as f32 * 0.005);
+    let humidity = read_u16(data[3], data[4]).map(|h| (h as f32 * 0.0025).min(100.0));
+    let pressure = read_u16(data[5], data[6]).map(|p| (p as f32 + 50000.0) / 100.0);
 
-        // Decode pressure: unsigned 16-bit integer + 50000 Pa, convert to hPa
-        let pressure = (u16::from_be_bytes([data[5], data[6]]) as f32 + 50000.0) / 100.0;
+    // Decode PM2.5: unsigned 16-bit integer * 0.1 µg/m³
+    let pm2_5 = read_u16(data[7], data[8]).map(|p| p as f32 * 0.1);
 
-        // Decode acceleration values: signed 16-bit integers * 0.001 g
-        let acc_x = i16::from_be_bytes([data[7], data[8]]) as f32 * 0.001;
-        let acc_y = i16::from_be_bytes([data[9], data[10]]) as f32 * 0.001;
-        let acc_z = i16::from_be_bytes([data[11], data[12]]) as f32 * 0.001;
+    // Decode CO2: unsigned 16-bit integer in ppm
+    let co2 = read_u16(data[9], data[10]);
 
-        // Movement counter: increments when significant movement is detected (sensor flips)
-        let movement_counter = data[15];
+    // VOC and NOx indices are 9-bit values split between their own byte and the flags
+    let flags = data[16];
+    let voc_index = read_index(data[11], flags >> 6);
+    let nox_index = read_index(data[12], flags >> 7);
 
-        // Create RuuviData with proper rounding for display
-        Ok(RuuviData {
-            temperature: (temperature * 100.0).round() / 100.0,
-            humidity: (humidity * 100.0).round() / 100.0,
-            pressure: (pressure * 100.0).round() / 100.0,
-            acceleration_x: (acc_x * 1000.0).round() / 1000.0,
-            acceleration_y: (acc_y * 1000.0).round() / 1000.0,
-            acceleration_z: (acc_z * 1000.0).round() / 1000.0,
-            movement_counter,
-        })
-    })() {
-        Ok(data) => Some(data),
-        Err(e) => {
-            error!("Error decoding format 5 data: {}", e);
-            None
-        }
+    // Luminosity is encoded logarithmically over the range 0-65535 lux
+    let luminosity = Some(data[13])
+        .filter(|&l| l != INVALID_U8)
+        .map(|l| (l as f32 * 65536.0f32.ln() / 254.0).exp() - 1.0);
+
+    AirQualityData {
+        temperature: temperature.map(|t| (t * 100.0).round() / 100.0),
+        humidity: humidity.map(|h| (h * 100.0).round() / 100.0),
+        pressure: pressure.map(|p| (p * 100.0).round() / 100.0),
+        pm1_0: None,
+        pm2_5: pm2_5.map(|p| (p * 10.0).round() / 10.0),
+        pm4_0: None,
+        pm10_0: None,
+        co2,
+        voc_index,
+        nox_index,
+        luminosity: luminosity.map(|l| (l * 100.0).round() / 100.0),
+        measurement_sequence: Some(data[15] as u32),
+        adapter: None,
+        received_at: None,
+    }
+}
+
+/// Read a 9-bit VOC or NOx index from its high byte and the flag bit holding
+/// bit 0, treating 0x1FF as "not available"
+fn read_index(high: u8, flag_bit: u8) -> Option<u16> {
+    Some(((high as u16) << 1) | (flag_bit & 0x01) as u16).filter(|&i| i != INVALID_INDEX)
+}
+
+/// Decode Ruuvi Air manufacturer data format E1 into structured data
+///
+/// Ruuvi Air extended data format E1 uses a 40-byte payload with the following structure:
+/// - Byte 0: Data format (0xE1)
+/// - Bytes 1-2: Temperature (signed 16-bit, 0.005°C resolution)
+/// - Bytes 3-4: Humidity (unsigned 16-bit, 0.0025% resolution)
+/// - Bytes 5-6: Pressure (unsigned 16-bit, +50000 Pa offset, 1 Pa resolution)
+/// - Bytes 7-8: PM1.0 (unsigned 16-bit, 0.1 µg/m³ resolution)
+/// - Bytes 9-10: PM2.5 (unsigned 16-bit, 0.1 µg/m³ resolution)
+/// - Bytes 11-12: PM4.0 (unsigned 16-bit, 0.1 µg/m³ resolution)
+/// - Bytes 13-14: PM10 (unsigned 16-bit, 0.1 µg/m³ resolution)
+/// - Bytes 15-16: CO2 (unsigned 16-bit, ppm)
+/// - Byte 17: VOC index, bits 8-1 (bit 0 in flags)
+/// - Byte 18: NOx index, bits 8-1 (bit 0 in flags)
+/// - Bytes 19-21: Luminosity (unsigned 24-bit, 0.01 lux resolution)
+/// - Bytes 22-24: Reserved
+/// - Bytes 25-27: Measurement sequence number
+/// - Byte 28: Flags (bit 6: VOC index bit 0, bit 7: NOx index bit 0)
+/// - Bytes 29-33: Reserved
+/// - Bytes 34-39: MAC address (see decode_mac)
+///
+/// Fields holding their "not available" sentinel value (0x8000 for temperature,
+/// 0xFFFF for the other 16-bit values, 0x1FF for the VOC and NOx indices and
+/// 0xFFFFFF for luminosity and the sequence number) are left empty.
+fn decode_format_e1(data: &[u8]) -> AirQualityData {
+    // Decode temperature, humidity and pressure exactly as in format 5
+    let temperature = read_i16(data[1], data[2]).map(|t| t as f32 * 0.005);
+    let humidity = read_u16(data[3], data[4]).map(|h| (h as f32 * 0.0025).min(100.0));
+    let pressure = read_u16(data[5], data[6]).map(|p| (p as f32 + 50000.0) / 100.0);
+
+    // Decode particulate matter: unsigned 16-bit integers * 0.1 µg/m³
+    let pm1_0 = read_u16(data[7], data[8]).map(|p| p as f32 * 0.1);
+    let pm2_5 = read_u16(data[9], data[10]).map(|p| p as f32 * 0.1);
+    let pm4_0 = read_u16(data[11], data[12]).map(|p| p as f32 * 0.1);
+    let pm10_0 = read_u16(data[13], data[14]).map(|p| p as f32 * 0.1);
+
+    // Decode CO2: unsigned 16-bit integer in ppm
+    let co2 = read_u16(data[15], data[16]);
+
+    // VOC and NOx indices are 9-bit values split between their own byte and the flags
+    let flags = data[28];
+    let voc_index = read_index(data[17], flags >> 6);
+    let nox_index = read_index(data[18], flags >> 7);
+
+    // Decode luminosity: unsigned 24-bit integer * 0.01 lux
+    let luminosity = read_u24(data[19], data[20], data[21]).map(|l| l as f32 * 0.01);
+
+    // Measurement sequence number: unsigned 24-bit integer
+    let measurement_sequence = read_u24(data[25], data[26], data[27]);
+
+    AirQualityData {
+        temperature: temperature.map(|t| (t * 100.0).round() / 100.0),
+        humidity: humidity.map(|h| (h * 100.0).round() / 100.0),
+        pressure: pressure.map(|p| (p * 100.0).round() / 100.0),
+        pm1_0: pm1_0.map(|p| (p * 10.0).round() / 10.0),
+        pm2_5: pm2_5.map(|p| (p * 10.0).round() / 10.0),
+        pm4_0: pm4_0.map(|p| (p * 10.0).round() / 10.0),
+        pm10_0: pm10_0.map(|p| (p * 10.0).round() / 10.0),
+        co2,
+        voc_index,
+        nox_index,
+        luminosity: luminosity.map(|l| (l * 100.0).round() / 100.0),
+        measurement_sequence,
+        adapter: None,
+        received_at: None,
     }
 }
 
-/// Scan for configured RuuviTag sensors and collect their data
+/// Read an unsigned 24-bit big-endian value, treating 0xFFFFFF as "not available"
+fn read_u24(high: u8, middle: u8, low: u8) -> Option<u32> {
+    Some(u32::from_be_bytes([0, high, middle, low])).filter(|&v| v != INVALID_U24)
+}
+
+/// Run a long-lived Bluetooth scanner that forwards every new Ruuvi advertisement
 ///
-/// This function performs a Bluetooth Low Energy scan to discover configured
-/// RuuviTag sensors and decode their advertised data. The scan runs for a
-/// fixed duration and returns all valid readings found.
+/// The scanner keeps a single BlueZ session and discovery running and watches
+/// each discovered device for manufacturer data property changes. Every
+/// distinct Ruuvi payload is sent over the channel as soon as it is received,
+/// so tags advertising every second contribute every measurement instead of
+/// one cached payload per poll. Payloads cached by BlueZ before the scanner
+/// started are never forwarded.
 ///
 /// # Arguments
-/// * `config` - Configuration containing sensor MAC addresses to look for
+/// * `adapter_name` - Name of the Bluetooth adapter to scan on (e.g. hci1), or None for the default adapter
+/// * `tx` - Channel to the aggregation loop
+/// * `health` - Shared health status, marked healthy once discovery is running
 ///
 /// # Returns
-/// Result containing HashMap of sensor MAC -> RuuviData, or error if scan fails
-pub async fn scan_for_ruuvitags(
-    config: &SensorConfig,
-) -> Result<HashMap<String, RuuviData>, Box<dyn std::error::Error>> {
-    let mut data = HashMap::new();
-
+/// Error if the Bluetooth session or discovery fails. Returns Ok(()) only when
+/// the receiving side of the channel has been closed.
+pub async fn run_scanner(
+    adapter_name: Option<&str>,
+    tx: mpsc::Sender<Advertisement>,
+    health: &AdapterHealthMap,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Initialize Bluetooth session
     let session = match bluer::Session::new().await {
         Ok(session) => session,
@@ -107,11 +668,19 @@ pub async fn scan_for_ruuvitags(
         }
     };
 
-    // Get the default Bluetooth adapter
-    let adapter = match session.default_adapter().await {
+    // Get the configured Bluetooth adapter, or the default one
+    let adapter = match adapter_name {
+        Some(name) => session.adapter(name),
+        None => session.default_adapter().await,
+    };
+    let adapter = match adapter {
         Ok(adapter) => adapter,
         Err(e) => {
-            error!("Failed to get default Bluetooth adapter: {}", e);
+            error!(
+                "Failed to get Bluetooth adapter {}: {}",
+                adapter_name.unwrap_or(DEFAULT_ADAPTER_LABEL),
+                e
+            );
             return Err(e.into());
         }
     };
@@ -125,7 +694,7 @@ pub async fn scan_for_ruuvitags(
     // Configure discovery filter for Low Energy devices only
     let filter = bluer::DiscoveryFilter {
         transport: bluer::DiscoveryTransport::Le, // Bluetooth Low Energy only
-        duplicate_data: false,                    // Filter out duplicate advertisements
+        duplicate_data: true, // Report manufacturer data every time it is received
         ..Default::default()
     };
 
@@ -134,69 +703,514 @@ pub async fn scan_for_ruuvitags(
         warn!("Failed to set discovery filter: {}", e);
     }
 
-    // Start device discovery in background
-    let discovery_handle = {
-        match adapter.discover_devices().await {
-            Ok(discovery_stream) => tokio::spawn(async move {
-                let mut stream = discovery_stream;
-                while let Some(event) = stream.next().await {
-                    debug!("Discovery event: {:?}", event);
+    // Start device discovery, which keeps running as long as the stream is held
+    let mut discovery = match adapter.discover_devices().await {
+        Ok(discovery) => discovery,
+        Err(e) => {
+            error!("Failed to start device discovery: {}", e);
+            return Err(e.into());
+        }
+    };
+
+    info!("Bluetooth scanner started on adapter {}", adapter.name());
+    update_health(
+        health,
+        adapter_name.unwrap_or(DEFAULT_ADAPTER_LABEL),
+        |status| status.health = AdapterHealth::Healthy,
+    );
+
+    // One watcher task per discovered device
+    let mut watchers: HashMap<bluer::Address, JoinHandle<()>> = HashMap::new();
+
+    while let Some(event) = discovery.next().await {
+        match event {
+            AdapterEvent::DeviceAdded(addr) => {
+                if watchers.get(&addr).is_some_and(|w| !w.is_finished()) {
+                    continue;
+                }
+                let device = match adapter.device(addr) {
+                    Ok(device) => device,
+                    Err(_) => continue,
+                };
+                let tx = tx.clone();
+                let adapter_name = adapter.name().to_string();
+                let watcher = tokio::spawn(async move {
+                    if let Err(e) = watch_device(device, adapter_name, tx).await {
+                        debug!("Stopped watching device {}: {}", addr, e);
+                    }
+                });
+                watchers.insert(addr, watcher);
+            }
+            AdapterEvent::DeviceRemoved(addr) => {
+                if let Some(watcher) = watchers.remove(&addr) {
+                    watcher.abort();
                 }
-            }),
-            Err(e) => {
-                error!("Failed to start device discovery: {}", e);
-                return Err(e.into());
+            }
+            AdapterEvent::PropertyChanged(property) => {
+                debug!("Adapter property changed: {:?}", property);
             }
         }
-    };
 
-    // Let discovery run for the configured duration
-    sleep(Duration::from_secs(SCAN_DURATION_SECS)).await;
+        // Stop scanning once nobody is listening anymore
+        if tx.is_closed() {
+            break;
+        }
+    }
 
-    // Stop discovery
-    discovery_handle.abort();
+    for watcher in watchers.into_values() {
+        watcher.abort();
+    }
 
-    // Get all discovered device addresses
-    let devices = match adapter.device_addresses().await {
-        Ok(devices) => devices,
-        Err(e) => {
-            error!("Failed to get device addresses: {}", e);
-            return Err(e.into());
+    if tx.is_closed() {
+        Ok(())
+    } else {
+        Err("Bluetooth discovery ended unexpectedly".into())
+    }
+}
+
+/// Spawn one Bluetooth scanner per configured adapter as background tasks
+///
+/// All scanners send to the same channel, so readings heard by several
+/// adapters are merged and later de-duplicated by sequence number.
+///
+/// # Arguments
+/// * `adapters` - Names of the Bluetooth adapters to scan on, or empty for the default adapter
+/// * `tx` - Channel to the aggregation loop
+/// * `health` - Shared health status updated by the scanners
+pub fn spawn_scanners(
+    adapters: &[String],
+    tx: mpsc::Sender<Advertisement>,
+    health: &AdapterHealthMap,
+) -> Vec<JoinHandle<()>> {
+    if adapters.is_empty() {
+        return vec![spawn_scanner(None, tx, health.clone())];
+    }
+
+    adapters
+        .iter()
+        .map(|name| spawn_scanner(Some(name.clone()), tx.clone(), health.clone()))
+        .collect()
+}
+
+/// Spawn the Bluetooth scanner for a single adapter as a background task
+///
+/// The scanner is restarted whenever it fails, for example when the adapter
+/// disappears or bluetoothd restarts. Restarts are delayed with exponential
+/// backoff, every restart acquires a new BlueZ session, and the adapter is
+/// power-cycled after repeated consecutive failures.
+///
+/// # Arguments
+/// * `adapter_name` - Name of the Bluetooth adapter to scan on, or None for the default adapter
+/// * `tx` - Channel to the aggregation loop
+/// * `health` - Shared health status updated by the scanner
+pub fn spawn_scanner(
+    adapter_name: Option<String>,
+    tx: mpsc::Sender<Advertisement>,
+    health: AdapterHealthMap,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let label = adapter_name.as_deref().unwrap_or(DEFAULT_ADAPTER_LABEL);
+
+        loop {
+            update_health(&health, label, |status| {
+                status.health = AdapterHealth::Starting
+            });
+
+            let started = Instant::now();
+            match run_scanner(adapter_name.as_deref(), tx.clone(), &health).await {
+                Ok(()) => break,
+                Err(e) => error!("Bluetooth scanner on adapter {} failed: {}", label, e),
+            }
+
+            let status = update_health(&health, label, |status| {
+                // A scanner that ran for a long time failed for a new reason
+                if started.elapsed() >= Duration::from_secs(SCANNER_STABLE_RUN_SECS) {
+                    status.consecutive_failures = 0;
+                }
+                status.consecutive_failures += 1;
+                status.total_failures += 1;
+                status.health = AdapterHealth::Failing;
+            });
+
+            // Repeated failures usually mean the adapter itself is stuck
+            if status.consecutive_failures % POWER_CYCLE_AFTER_FAILURES == 0 {
+                update_health(&health, label, |status| {
+                    status.health = AdapterHealth::Recovering;
+                    status.power_cycles += 1;
+                });
+                if let Err(e) = power_cycle_adapter(adapter_name.as_deref()).await {
+                    error!("Failed to power-cycle Bluetooth adapter {}: {}", label, e);
+                }
+            }
+
+            let delay = backoff_delay(status.consecutive_failures);
+            info!(
+                "Restarting Bluetooth scanner on adapter {} in {} seconds",
+                label,
+                delay.as_secs()
+            );
+            sleep(delay).await;
         }
+    })
+}
+
+/// Calculate the delay before restarting a scanner
+///
+/// Doubles with every consecutive failure, starting from
+/// SCANNER_BACKOFF_INITIAL_SECS and capped at SCANNER_BACKOFF_MAX_SECS.
+fn backoff_delay(consecutive_failures: u32) -> Duration {
+    let exponent = consecutive_failures.saturating_sub(1).min(16);
+    let secs = SCANNER_BACKOFF_INITIAL_SECS.saturating_mul(1 << exponent);
+    Duration::from_secs(secs.min(SCANNER_BACKOFF_MAX_SECS))
+}
+
+/// Power the adapter off and back on to recover it from a stuck state
+///
+/// Uses a fresh BlueZ session, so this also works after bluetoothd restarted.
+async fn power_cycle_adapter(adapter_name: Option<&str>) -> bluer::Result<()> {
+    let session = bluer::Session::new().await?;
+    let adapter = match adapter_name {
+        Some(name) => session.adapter(name)?,
+        None => session.default_adapter().await?,
     };
 
-    // Process each discovered device
-    for addr in devices {
-        let device = match adapter.device(addr) {
-            Ok(device) => device,
-            Err(_) => continue,
-        };
-
-        let addr_str = device.address().to_string().to_uppercase();
-
-        // Only process devices that are in our configuration
-        if config.tags.contains_key(&addr_str) {
-            match device.manufacturer_data().await {
-                Ok(Some(manufacturer_data)) => {
-                    if let Some(ruuvi_data) = manufacturer_data.get(&RUUVITAG_MANUFACTURER_ID) {
-                        // Decode the RuuviTag data
-                        if let Some(sensor_data) = decode_ruuvi_data(ruuvi_data) {
-                            let log_data = sensor_data.clone();
-                            data.insert(addr_str.clone(), sensor_data);
-                            debug!("Received data from {}: temp={:.2}°C, humidity={:.2}%, pressure={:.2} hPa",
-                              addr_str, log_data.temperature, log_data.humidity, log_data.pressure/*, log_data.acceleration_x, log_data.acceleration_y, log_data.acceleration_z, log_data.movement_counter*/);
-                        }
-                    }
-                }
-                Ok(None) => {
-                    debug!("No manufacturer data for {}", addr_str);
+    info!("Power-cycling Bluetooth adapter {}", adapter.name());
+    adapter.set_powered(false).await?;
+    sleep(Duration::from_secs(POWER_CYCLE_OFF_SECS)).await;
+    adapter.set_powered(true).await
+}
+
+/// Forward manufacturer data changes of a single device as advertisements
+///
+/// Only Ruuvi manufacturer data is forwarded, and only when the payload differs
+/// from the previous one received from the device.
+async fn watch_device(
+    device: bluer::Device,
+    adapter_name: String,
+    tx: mpsc::Sender<Advertisement>,
+) -> bluer::Result<()> {
+    let address = device.address().to_string().to_uppercase();
+    let random_address = matches!(
+        device.address_type().await,
+        Ok(bluer::AddressType::LeRandom)
+    );
+    let mut rssi = device.rssi().await.ok().flatten();
+    let mut last_payload: Option<Vec<u8>> = None;
+
+    let mut events = device.events().await?;
+    while let Some(DeviceEvent::PropertyChanged(property)) = events.next().await {
+        match property {
+            DeviceProperty::Rssi(value) => rssi = Some(value),
+            DeviceProperty::ManufacturerData(manufacturer_data) => {
+                let Some(payload) = manufacturer_data.get(&RUUVITAG_MANUFACTURER_ID) else {
+                    continue;
+                };
+                if last_payload.as_ref() == Some(payload) {
+                    continue;
                 }
-                Err(e) => {
-                    debug!("Failed to get manufacturer data for {}: {}", addr_str, e);
+                last_payload = Some(payload.clone());
+
+                let advertisement = Advertisement {
+                    received_at: OffsetDateTime::now_utc(),
+                    adapter: adapter_name.clone(),
+                    address: address.clone(),
+                    random_address,
+                    rssi,
+                    payload: payload.clone(),
+                };
+                if tx.send(advertisement).await.is_err() {
+                    break;
                 }
             }
+            _ => {}
         }
     }
 
-    Ok(data)
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::parse_hex;
+
+    /// Decode a hex test vector as a RuuviTag payload
+    fn decode_vector(hex: &str, key: Option<&[u8; 16]>) -> Result<RuuviData, DecodeError> {
+        decode_ruuvi_data(&parse_hex(hex).unwrap(), key)
+    }
+
+    fn assert_close(actual: Option<f32>, expected: f32) {
+        let actual = actual.expect("value should be present");
+        assert!(
+            (actual - expected).abs() < 0.011,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    // Test vectors published in the Ruuvi sensor protocol documentation
+
+    #[test]
+    fn format_3_valid() {
+        let data = decode_vector("03291A1ECE1EFC18F94202CA0B53", None).unwrap();
+        assert_close(data.humidity, 20.5);
+        assert_close(data.temperature, 26.3);
+        assert_close(data.pressure, 1027.66);
+        assert_close(data.acceleration_x, -1.0);
+        assert_close(data.acceleration_y, -1.726);
+        assert_close(data.acceleration_z, 0.714);
+        assert_eq!(data.battery_voltage, Some(2899));
+        assert_eq!(data.measurement_sequence, None);
+    }
+
+    #[test]
+    fn format_3_max() {
+        let data = decode_vector("03FF7F63FFFF7FFF7FFF7FFFFFFF", None).unwrap();
+        assert_close(data.humidity, 100.0);
+        assert_close(data.temperature, 127.99);
+        assert_close(data.pressure, 1155.35);
+        assert_close(data.acceleration_x, 32.767);
+        assert_close(data.acceleration_y, 32.767);
+        assert_close(data.acceleration_z, 32.767);
+        assert_eq!(data.battery_voltage, Some(65535));
+    }
+
+    #[test]
+    fn format_3_min() {
+        let data = decode_vector("0300FF6300008001800180010000", None).unwrap();
+        assert_close(data.humidity, 0.0);
+        assert_close(data.temperature, -127.99);
+        assert_close(data.pressure, 500.0);
+        assert_close(data.acceleration_x, -32.767);
+        assert_close(data.acceleration_y, -32.767);
+        assert_close(data.acceleration_z, -32.767);
+        assert_eq!(data.battery_voltage, Some(0));
+    }
+
+    #[test]
+    fn format_5_valid() {
+        let hex = "0512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F";
+        let data = decode_vector(hex, None).unwrap();
+        assert_close(data.temperature, 24.3);
+        assert_close(data.humidity, 53.49);
+        assert_close(data.pressure, 1000.44);
+        assert_close(data.acceleration_x, 0.004);
+        assert_close(data.acceleration_y, -0.004);
+        assert_close(data.acceleration_z, 1.036);
+        assert_eq!(data.tx_power, Some(4));
+        assert_eq!(data.battery_voltage, Some(2977));
+        assert_eq!(data.movement_counter, Some(66));
+        assert_eq!(data.measurement_sequence, Some(205));
+        assert_eq!(
+            decode_mac(&parse_hex(hex).unwrap()).as_deref(),
+            Some("CB:B8:33:4C:88:4F")
+        );
+    }
+
+    #[test]
+    fn format_5_max() {
+        let data = decode_vector("057FFFFFFEFFFE7FFF7FFF7FFFFFDEFEFFFECBB8334C884F", None).unwrap();
+        assert_close(data.temperature, 163.835);
+        assert_close(data.humidity, 100.0);
+        assert_close(data.pressure, 1155.34);
+        assert_close(data.acceleration_x, 32.767);
+        assert_close(data.acceleration_y, 32.767);
+        assert_close(data.acceleration_z, 32.767);
+        assert_eq!(data.tx_power, Some(20));
+        assert_eq!(data.battery_voltage, Some(3646));
+        assert_eq!(data.movement_counter, Some(254));
+        assert_eq!(data.measurement_sequence, Some(65534));
+    }
+
+    #[test]
+    fn format_5_min() {
+        let data = decode_vector("058001000000008001800180010000000000CBB8334C884F", None).unwrap();
+        assert_close(data.temperature, -163.835);
+        assert_close(data.humidity, 0.0);
+        assert_close(data.pressure, 500.0);
+        assert_close(data.acceleration_x, -32.767);
+        assert_close(data.acceleration_y, -32.767);
+        assert_close(data.acceleration_z, -32.767);
+        assert_eq!(data.tx_power, Some(-40));
+        assert_eq!(data.battery_voltage, Some(1600));
+        assert_eq!(data.movement_counter, Some(0));
+        assert_eq!(data.measurement_sequence, Some(0));
+    }
+
+    #[test]
+    fn format_5_invalid() {
+        let hex = "058000FFFFFFFF800080008000FFFFFFFFFFFFFFFFFFFFFF";
+        assert_eq!(
+            decode_vector(hex, None).unwrap_err(),
+            DecodeError::NoValidMeasurements
+        );
+        assert_eq!(decode_mac(&parse_hex(hex).unwrap()), None);
+    }
+
+    #[test]
+    fn rejects_malformed_payloads() {
+        assert_eq!(
+            decode_ruuvi_data(&[], None).unwrap_err(),
+            DecodeError::Empty
+        );
+        assert_eq!(
+            decode_vector("0512FC5394", None).unwrap_err(),
+            DecodeError::InvalidLength {
+                format: 5,
+                length: 5
+            }
+        );
+        assert_eq!(
+            decode_vector("0412FC5394", None).unwrap_err(),
+            DecodeError::UnsupportedFormat(4)
+        );
+    }
+
+    #[test]
+    fn crc8_check_value() {
+        // Standard check value of CRC-8 with polynomial 0x07 and initial value 0x00
+        assert_eq!(crc8(b"123456789"), 0xF4);
+    }
+
+    const FORMAT_8_KEY: [u8; 16] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE,
+        0xFF,
+    ];
+
+    /// Build a format 8 payload by encrypting a plaintext measurement block
+    fn format_8_payload(block: &[u8; 16], key: &[u8; 16]) -> Vec<u8> {
+        let mut crypter = Crypter::new(Cipher::aes_128_ecb(), Mode::Encrypt, key, None).unwrap();
+        crypter.pad(false);
+        let mut encrypted = [0u8; 32];
+        let count = crypter.update(block, &mut encrypted).unwrap();
+        assert_eq!(count, 16);
+
+        let mut payload = vec![DATA_FORMAT_8];
+        payload.extend_from_slice(&encrypted[..16]);
+        payload.push(crc8(block));
+        payload.extend_from_slice(&parse_hex("CBB8334C884F").unwrap());
+        payload
+    }
+
+    #[test]
+    fn format_8_valid() {
+        // Same measurements as the format 5 valid vector, reserved bytes zeroed
+        let mut block = [0u8; 16];
+        block[..11].copy_from_slice(&parse_hex("12FC5394C37CAC364200CD").unwrap());
+        let payload = format_8_payload(&block, &FORMAT_8_KEY);
+
+        let data = decode_ruuvi_data(&payload, Some(&FORMAT_8_KEY)).unwrap();
+        assert_close(data.temperature, 24.3);
+        assert_close(data.humidity, 53.49);
+        assert_close(data.pressure, 1000.44);
+        assert_eq!(data.acceleration_x, None);
+        assert_eq!(data.tx_power, Some(4));
+        assert_eq!(data.battery_voltage, Some(2977));
+        assert_eq!(data.movement_counter, Some(66));
+        assert_eq!(data.measurement_sequence, Some(205));
+        assert_eq!(decode_mac(&payload).as_deref(), Some("CB:B8:33:4C:88:4F"));
+    }
+
+    #[test]
+    fn format_8_invalid_values() {
+        let mut block = [0u8; 16];
+        block[..11].copy_from_slice(&parse_hex("8000FFFFFFFFFFFFFFFFFF").unwrap());
+        let payload = format_8_payload(&block, &FORMAT_8_KEY);
+
+        assert_eq!(
+            decode_ruuvi_data(&payload, Some(&FORMAT_8_KEY)).unwrap_err(),
+            DecodeError::NoValidMeasurements
+        );
+    }
+
+    #[test]
+    fn format_8_requires_matching_key() {
+        let mut block = [0u8; 16];
+        block[..11].copy_from_slice(&parse_hex("12FC5394C37CAC364200CD").unwrap());
+        let payload = format_8_payload(&block, &FORMAT_8_KEY);
+
+        let mut wrong_key = FORMAT_8_KEY;
+        wrong_key[0] ^= 0x01;
+        assert_eq!(
+            decode_ruuvi_data(&payload, Some(&wrong_key)).unwrap_err(),
+            DecodeError::CrcMismatch
+        );
+        assert_eq!(
+            decode_ruuvi_data(&payload, None).unwrap_err(),
+            DecodeError::MissingKey
+        );
+    }
+
+    /// Decode a hex test vector as a Ruuvi Air payload
+    fn decode_air_vector(hex: &str) -> Result<AirQualityData, DecodeError> {
+        decode_air_quality_data(&parse_hex(hex).unwrap())
+    }
+
+    #[test]
+    fn format_6_valid() {
+        let data = decode_air_vector("06170C5668C79E007000C90501D9FFCD004C884F").unwrap();
+        assert_close(data.temperature, 29.5);
+        assert_close(data.humidity, 55.3);
+        assert_close(data.pressure, 1011.02);
+        assert_close(data.pm2_5, 11.2);
+        assert_eq!(data.pm1_0, None);
+        assert_eq!(data.co2, Some(201));
+        assert_eq!(data.voc_index, Some(10));
+        assert_eq!(data.nox_index, Some(2));
+        assert!((data.luminosity.unwrap() - 13027.0).abs() < 20.0);
+        assert_eq!(data.measurement_sequence, Some(205));
+    }
+
+    #[test]
+    fn format_6_invalid() {
+        let hex = "068000FFFFFFFFFFFFFFFFFFFFFFFFFFC04C884F";
+        assert_eq!(
+            decode_air_vector(hex).unwrap_err(),
+            DecodeError::NoValidMeasurements
+        );
+    }
+
+    #[test]
+    fn format_6_partially_invalid() {
+        // Only CO2 and the VOC index are missing
+        let data = decode_air_vector("06170C5668C79E0070FFFFFF01D9FFCD404C884F").unwrap();
+        assert_close(data.temperature, 29.5);
+        assert_eq!(data.co2, None);
+        assert_eq!(data.voc_index, None);
+        assert_eq!(data.nox_index, Some(2));
+    }
+
+    #[test]
+    fn format_e1_valid() {
+        let hex =
+            "E1170C5668C79E0065007004BD11CA00C90A0213E0ACFFFFFFDECDEE10FFFFFFFFFFCBB8334C884F";
+        let data = decode_air_vector(hex).unwrap();
+        assert_close(data.temperature, 29.5);
+        assert_close(data.humidity, 55.3);
+        assert_close(data.pressure, 1011.02);
+        assert_close(data.pm1_0, 10.1);
+        assert_close(data.pm2_5, 11.2);
+        assert_close(data.pm4_0, 121.3);
+        assert_close(data.pm10_0, 455.4);
+        assert_eq!(data.co2, Some(201));
+        assert_eq!(data.voc_index, Some(20));
+        assert_eq!(data.nox_index, Some(4));
+        assert_close(data.luminosity, 13027.0);
+        assert_eq!(data.measurement_sequence, Some(14601710));
+        assert_eq!(
+            decode_mac(&parse_hex(hex).unwrap()).as_deref(),
+            Some("CB:B8:33:4C:88:4F")
+        );
+    }
+
+    #[test]
+    fn format_e1_invalid() {
+        let hex =
+            "E18000FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFC0FFFFFFFFFFFFFFFFFFFFFF";
+        assert_eq!(
+            decode_air_vector(hex).unwrap_err(),
+            DecodeError::NoValidMeasurements
+        );
+    }
 }
@@ -0,0 +1,73 @@
+/// Time abstraction so that collection intervals can follow replayed timestamps
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+use tokio::time::sleep;
+
+/// Source of the current time for the main loop
+///
+/// The main loop never reads the system time directly, so replayed data is
+/// aggregated into the intervals given by its original timestamps.
+pub trait Clock {
+    /// Current time
+    fn now(&self) -> OffsetDateTime;
+
+    /// Wait until the given time has been reached
+    fn sleep_until(&self, deadline: OffsetDateTime) -> impl Future<Output = ()> + Send;
+}
+
+/// Clock following the system time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+
+    async fn sleep_until(&self, deadline: OffsetDateTime) {
+        let remaining = deadline - OffsetDateTime::now_utc();
+        if let Ok(remaining) = std::time::Duration::try_from(remaining) {
+            sleep(remaining).await;
+        }
+    }
+}
+
+/// Clock driven by the timestamps of replayed advertisements
+///
+/// The replay source advances the clock to the timestamp of every
+/// advertisement it yields. Time never passes on its own, so waiting only
+/// completes when the deadline has already been reached.
+#[derive(Debug, Clone)]
+pub struct ReplayClock {
+    now: Arc<Mutex<OffsetDateTime>>,
+}
+
+impl ReplayClock {
+    /// Create a clock starting at the given time
+    pub fn new(start: OffsetDateTime) -> Self {
+        ReplayClock {
+            now: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    /// Advance the clock, ignoring timestamps earlier than the current time
+    pub fn advance_to(&self, time: OffsetDateTime) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        if time > *now {
+            *now = time;
+        }
+    }
+}
+
+impl Clock for ReplayClock {
+    fn now(&self) -> OffsetDateTime {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn sleep_until(&self, deadline: OffsetDateTime) {
+        if self.now() < deadline {
+            std::future::pending::<()>().await;
+        }
+    }
+}
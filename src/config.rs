@@ -1,6 +1,44 @@
 /// Configuration management via environment variables
 use std::collections::HashMap;
 use std::env;
+use time::UtcOffset;
+
+use crate::window::WindowTimeZone;
+
+// Defaults for optional settings
+const DEFAULT_AGGREGATION_WINDOW_SECS: u64 = 1800; // 30 minutes
+const DEFAULT_CAPTURE_MAX_BYTES: u64 = 10 * 1024 * 1024; // Rotate capture files at 10 MiB
+const DEFAULT_CAPTURE_MAX_FILES: u32 = 5; // Number of rotated capture files to keep
+const DEFAULT_GATEWAY_LISTEN: &str = "127.0.0.1:8080"; // Ruuvi Gateway HTTP server address, local only
+const DEFAULT_MQTT_TOPIC: &str = "ruuvi/#"; // Topics Ruuvi Gateways publish advertisements on
+const DEFAULT_HAMPEL_THRESHOLD: f32 = 3.0; // Outlier limit in (MAD-estimated) standard deviations
+const DEFAULT_HISTORY_MAX_DAYS: u32 = 10; // RuuviTag firmware 3.x keeps about 10 days of history
+
+/// Where advertisements are read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    /// BlueZ scanners on the configured Bluetooth adapters
+    Bluetooth,
+    /// Generated advertisements for the configured tags, for development without hardware
+    Simulated,
+    /// Advertisements recorded in a capture file, aggregated by their original timestamps
+    Replay,
+    /// Advertisements relayed by Ruuvi Gateways to the embedded HTTP server
+    Gateway,
+    /// Advertisements published by Ruuvi Gateways or BLE proxies to an MQTT broker
+    Mqtt,
+}
+
+/// How Ruuvi devices that are not in the sensor configuration are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscoveryMode {
+    /// Unconfigured devices are ignored
+    Off,
+    /// Unconfigured devices are logged once per collection interval
+    Log,
+    /// Unconfigured devices are logged and stored in the discovered_tags table
+    Store,
+}
 
 /// Application configuration loaded from environment variables
 ///
@@ -13,6 +51,47 @@ pub struct SensorConfig {
     pub tags: HashMap<String, String>,
     /// PostgreSQL connection string with SSL parameters
     pub database_url: String,
+    /// Map of sensor MAC addresses to AES-128 keys for encrypted data format 8
+    /// Key: MAC address (uppercase), Value: 16-byte decryption key
+    pub encryption_keys: HashMap<String, [u8; 16]>,
+    /// Names of the Bluetooth adapters to scan on (e.g. hci0, hci1)
+    /// Empty means the system default adapter
+    pub bluetooth_adapters: Vec<String>,
+    /// Handling of Ruuvi devices that are not listed in `tags`
+    pub discovery_mode: DiscoveryMode,
+    /// Source of the advertisements
+    pub reading_source: SourceKind,
+    /// Path of the JSON Lines file raw advertisements are captured to, None to disable capturing
+    pub capture_file: Option<String>,
+    /// Size in bytes at which the capture file is rotated
+    pub capture_max_bytes: u64,
+    /// Number of rotated capture files to keep
+    pub capture_max_files: u32,
+    /// Path of the capture file to replay when the reading source is Replay
+    pub replay_file: Option<String>,
+    /// Acceleration factor for replay, 0 to replay as fast as possible
+    pub replay_speed: f64,
+    /// Socket address the Ruuvi Gateway HTTP server listens on
+    pub gateway_listen: String,
+    /// Bearer token Ruuvi Gateways must send, None to accept unauthenticated posts
+    pub gateway_token: Option<String>,
+    /// URL of the MQTT broker when the reading source is Mqtt
+    pub mqtt_url: Option<String>,
+    /// MQTT topic filter advertisements are published on
+    pub mqtt_topic: String,
+    /// Backfill missing data from the tags' history logs at startup (Bluetooth source only)
+    pub history_sync: bool,
+    /// Maximum age in days of history log entries to backfill
+    pub history_max_days: u32,
+    /// Lengths in seconds of the aggregation windows, each stored with its own resolution
+    /// The first window also drives discovery reporting and source health logging
+    pub aggregation_windows: Vec<u64>,
+    /// Time zone of the local time aggregation windows are aligned to
+    pub window_time_zone: WindowTimeZone,
+    /// Reject outlying RuuviTag values before averaging
+    pub outlier_filter: bool,
+    /// Hampel filter limit in standard deviations, 0 to only reject values outside sensor ranges
+    pub hampel_threshold: f32,
 }
 
 impl SensorConfig {
@@ -23,6 +102,29 @@ impl SensorConfig {
     /// 2. Individual RUUVI_TAG_N_MAC and RUUVI_TAG_N_NAME variables (legacy)
     ///
     /// Also requires DATABASE_URL with PostgreSQL connection string.
+    /// Encryption keys for tags broadcasting data format 8 are read from
+    /// RUUVI_TAG_KEYS="MAC1=HexKey1,MAC2=HexKey2" (32 hex digits per key).
+    /// Bluetooth adapters to scan on are read from BLUETOOTH_ADAPTERS="hci0,hci1",
+    /// falling back to the default adapter if unset.
+    /// Unconfigured Ruuvi devices are reported according to DISCOVERY_MODE
+    /// ("off", "log" or "store", default "off").
+    /// READING_SOURCE selects where advertisements come from
+    /// ("bluetooth", "simulated" or "replay", default "bluetooth"). Replay reads
+    /// REPLAY_FILE, accelerated by REPLAY_SPEED (default 0, as fast as possible).
+    /// Gateway listens for Ruuvi Gateway POSTs on GATEWAY_LISTEN (default 127.0.0.1:8080),
+    /// requiring the bearer token GATEWAY_TOKEN if set.
+    /// Mqtt subscribes to MQTT_TOPIC (default ruuvi/#) on the broker at MQTT_URL.
+    /// Raw advertisements are captured to CAPTURE_FILE if set, rotated at
+    /// CAPTURE_MAX_BYTES (default 10 MiB) keeping CAPTURE_MAX_FILES (default 5).
+    /// HISTORY_SYNC=true backfills data missed while the service was down from the
+    /// tags' history logs, going back at most HISTORY_MAX_DAYS (default 10).
+    /// AGGREGATION_WINDOWS="1m,30m,1d" aggregates the readings over several window
+    /// lengths at once (units s, m, h or d, default 30m). Windows are aligned to
+    /// multiples of their length in the local time of WINDOW_TIME_ZONE (an IANA
+    /// time zone such as "Europe/Helsinki", following daylight saving time) or
+    /// WINDOW_UTC_OFFSET (a fixed offset such as "+02:00"), default UTC.
+    /// OUTLIER_FILTER=true rejects outlying RuuviTag values before averaging (default off),
+    /// using sensor range limits and a Hampel filter with HAMPEL_THRESHOLD (default 3.0).
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
         // Load environment variables
         dotenv::dotenv().ok();
@@ -79,6 +181,297 @@ impl SensorConfig {
             return Err("No RuuviTag sensors configured. Please set RUUVI_TAGS or RUUVI_TAG_<N>_MAC/RUUVI_TAG_<N>_NAME environment variables".into());
         }
 
-        Ok(SensorConfig { tags, database_url })
+        // Load optional per-tag encryption keys
+        let mut encryption_keys = HashMap::new();
+        if let Ok(ruuvi_tag_keys) = env::var("RUUVI_TAG_KEYS") {
+            for pair in ruuvi_tag_keys.split(',') {
+                let pair = pair.trim();
+                if pair.is_empty() {
+                    continue;
+                }
+                let (mac, key) = pair
+                    .split_once('=')
+                    .ok_or_else(|| format!("Invalid RUUVI_TAG_KEYS entry: '{}'", pair))?;
+                let mac = mac.trim();
+                let key = parse_encryption_key(key.trim()).ok_or_else(|| {
+                    format!(
+                        "Invalid encryption key for tag {}: expected 32 hex digits",
+                        mac
+                    )
+                })?;
+                println!("Found encryption key for tag {}", mac);
+                encryption_keys.insert(mac.to_string(), key);
+            }
+        }
+
+        // Load optional Bluetooth adapter selection
+        let mut bluetooth_adapters: Vec<String> = Vec::new();
+        if let Ok(adapters) = env::var("BLUETOOTH_ADAPTERS") {
+            for name in adapters.split(',') {
+                let name = name.trim();
+                if !name.is_empty() && !bluetooth_adapters.iter().any(|a| a == name) {
+                    println!("Using Bluetooth adapter: {}", name);
+                    bluetooth_adapters.push(name.to_string());
+                }
+            }
+        }
+
+        // Load optional discovery mode for unconfigured tags
+        let discovery_mode = match env::var("DISCOVERY_MODE") {
+            Ok(mode) => match mode.trim().to_lowercase().as_str() {
+                "" | "off" => DiscoveryMode::Off,
+                "log" => DiscoveryMode::Log,
+                "store" => DiscoveryMode::Store,
+                _ => {
+                    return Err(format!(
+                        "Invalid DISCOVERY_MODE '{}': expected off, log or store",
+                        mode
+                    )
+                    .into())
+                }
+            },
+            Err(_) => DiscoveryMode::Off,
+        };
+        if discovery_mode != DiscoveryMode::Off {
+            println!("Discovery mode: {:?}", discovery_mode);
+        }
+
+        // Load optional reading source selection
+        let reading_source = match env::var("READING_SOURCE") {
+            Ok(source) => match source.trim().to_lowercase().as_str() {
+                "" | "bluetooth" => SourceKind::Bluetooth,
+                "simulated" => SourceKind::Simulated,
+                "replay" => SourceKind::Replay,
+                "gateway" => SourceKind::Gateway,
+                "mqtt" => SourceKind::Mqtt,
+                _ => {
+                    return Err(format!(
+                        "Invalid READING_SOURCE '{}': expected bluetooth, simulated, \
+                         replay, gateway or mqtt",
+                        source
+                    )
+                    .into())
+                }
+            },
+            Err(_) => SourceKind::Bluetooth,
+        };
+        println!("Reading source: {:?}", reading_source);
+
+        // Load optional raw advertisement capture settings
+        let capture_file = env::var("CAPTURE_FILE")
+            .ok()
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty());
+        let capture_max_bytes = match env::var("CAPTURE_MAX_BYTES") {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid CAPTURE_MAX_BYTES '{}'", value))?,
+            Err(_) => DEFAULT_CAPTURE_MAX_BYTES,
+        };
+        let capture_max_files = match env::var("CAPTURE_MAX_FILES") {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid CAPTURE_MAX_FILES '{}'", value))?,
+            Err(_) => DEFAULT_CAPTURE_MAX_FILES,
+        };
+        if let Some(path) = &capture_file {
+            println!("Capturing raw advertisements to {}", path);
+        }
+
+        // Load replay settings, required when replaying a capture file
+        let replay_file = env::var("REPLAY_FILE")
+            .ok()
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty());
+        if reading_source == SourceKind::Replay && replay_file.is_none() {
+            return Err("READING_SOURCE=replay requires REPLAY_FILE to be set".into());
+        }
+        let replay_speed = match env::var("REPLAY_SPEED") {
+            Ok(value) => value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|speed| speed.is_finite() && *speed >= 0.0)
+                .ok_or_else(|| format!("Invalid REPLAY_SPEED '{}'", value))?,
+            Err(_) => 0.0,
+        };
+
+        // Load Ruuvi Gateway HTTP server address
+        let gateway_listen = env::var("GATEWAY_LISTEN")
+            .map(|listen| listen.trim().to_string())
+            .unwrap_or_else(|_| DEFAULT_GATEWAY_LISTEN.to_string());
+        let gateway_token = env::var("GATEWAY_TOKEN")
+            .ok()
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty());
+
+        // Load MQTT broker settings, required when subscribing to MQTT
+        let mqtt_url = env::var("MQTT_URL")
+            .ok()
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+        if reading_source == SourceKind::Mqtt && mqtt_url.is_none() {
+            return Err("READING_SOURCE=mqtt requires MQTT_URL to be set".into());
+        }
+        let mqtt_topic = env::var("MQTT_TOPIC")
+            .map(|topic| topic.trim().to_string())
+            .unwrap_or_else(|_| DEFAULT_MQTT_TOPIC.to_string());
+
+        // Load history log backfill settings
+        let history_sync = match env::var("HISTORY_SYNC") {
+            Ok(value) => match value.trim().to_lowercase().as_str() {
+                "" | "false" | "0" | "no" => false,
+                "true" | "1" | "yes" => true,
+                _ => return Err(format!("Invalid HISTORY_SYNC '{}'", value).into()),
+            },
+            Err(_) => false,
+        };
+        let history_max_days = match env::var("HISTORY_MAX_DAYS") {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid HISTORY_MAX_DAYS '{}'", value))?,
+            Err(_) => DEFAULT_HISTORY_MAX_DAYS,
+        };
+        if history_sync {
+            println!(
+                "History sync enabled, backfilling up to {} days",
+                history_max_days
+            );
+        }
+
+        // Load aggregation window lengths
+        let mut aggregation_windows: Vec<u64> = Vec::new();
+        if let Ok(windows) = env::var("AGGREGATION_WINDOWS") {
+            for window in windows.split(',') {
+                let window = window.trim();
+                if window.is_empty() {
+                    continue;
+                }
+                let seconds = parse_window_length(window)
+                    .ok_or_else(|| format!("Invalid AGGREGATION_WINDOWS entry: '{}'", window))?;
+                if !aggregation_windows.contains(&seconds) {
+                    aggregation_windows.push(seconds);
+                }
+            }
+        }
+        if aggregation_windows.is_empty() {
+            aggregation_windows.push(DEFAULT_AGGREGATION_WINDOW_SECS);
+        }
+        println!("Aggregation windows (seconds): {:?}", aggregation_windows);
+        let window_time_zone = match (env::var("WINDOW_TIME_ZONE"), env::var("WINDOW_UTC_OFFSET")) {
+            (Ok(_), Ok(_)) => {
+                return Err("Set only one of WINDOW_TIME_ZONE and WINDOW_UTC_OFFSET".into());
+            }
+            (Ok(name), Err(_)) => time_tz::timezones::get_by_name(name.trim())
+                .map(WindowTimeZone::Named)
+                .ok_or_else(|| format!("Unknown WINDOW_TIME_ZONE '{}'", name))?,
+            (Err(_), Ok(value)) => parse_utc_offset(value.trim())
+                .map(WindowTimeZone::Fixed)
+                .ok_or_else(|| format!("Invalid WINDOW_UTC_OFFSET '{}'", value))?,
+            (Err(_), Err(_)) => WindowTimeZone::Fixed(UtcOffset::UTC),
+        };
+
+        // Load outlier filter settings
+        let outlier_filter = match env::var("OUTLIER_FILTER") {
+            Ok(value) => match value.trim().to_lowercase().as_str() {
+                "" | "false" | "0" | "no" => false,
+                "true" | "1" | "yes" => true,
+                _ => return Err(format!("Invalid OUTLIER_FILTER '{}'", value).into()),
+            },
+            Err(_) => false,
+        };
+        let hampel_threshold = match env::var("HAMPEL_THRESHOLD") {
+            Ok(value) => value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|threshold| threshold.is_finite() && *threshold >= 0.0)
+                .ok_or_else(|| format!("Invalid HAMPEL_THRESHOLD '{}'", value))?,
+            Err(_) => DEFAULT_HAMPEL_THRESHOLD,
+        };
+        if outlier_filter {
+            println!(
+                "Outlier filter enabled, Hampel threshold {}",
+                hampel_threshold
+            );
+        }
+
+        Ok(SensorConfig {
+            tags,
+            database_url,
+            encryption_keys,
+            bluetooth_adapters,
+            discovery_mode,
+            reading_source,
+            capture_file,
+            capture_max_bytes,
+            capture_max_files,
+            replay_file,
+            replay_speed,
+            gateway_listen,
+            gateway_token,
+            mqtt_url,
+            mqtt_topic,
+            history_sync,
+            history_max_days,
+            aggregation_windows,
+            window_time_zone,
+            outlier_filter,
+            hampel_threshold,
+        })
+    }
+}
+
+/// Parse a window length such as "90s", "1m", "6h" or "1d" into seconds
+///
+/// A number without a unit is taken as seconds. Zero-length windows are rejected.
+fn parse_window_length(length: &str) -> Option<u64> {
+    let (number, multiplier) = match length.char_indices().last()? {
+        (i, 's') => (&length[..i], 1),
+        (i, 'm') => (&length[..i], 60),
+        (i, 'h') => (&length[..i], 3600),
+        (i, 'd') => (&length[..i], 86400),
+        _ => (length, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .filter(|&seconds| seconds > 0)
+}
+
+/// Parse a UTC offset such as "+02:00", "-05:30", "+3" or "UTC"
+fn parse_utc_offset(offset: &str) -> Option<UtcOffset> {
+    if offset.is_empty() || offset.eq_ignore_ascii_case("utc") || offset == "Z" {
+        return Some(UtcOffset::UTC);
+    }
+    let (sign, rest) = if let Some(rest) = offset.strip_prefix('+') {
+        (1, rest)
+    } else {
+        (-1, offset.strip_prefix('-')?)
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let hours: i8 = hours.parse().ok()?;
+    let minutes: i8 = minutes.parse().ok()?;
+    if !(0..60).contains(&minutes) {
+        return None;
+    }
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+/// Parse a 128-bit AES key from a string of 32 hexadecimal digits
+fn parse_encryption_key(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut key = [0u8; 16];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
     }
+    Some(key)
 }
@@ -53,6 +53,25 @@ pub async fn execute_with_retry<F, Fut>(database_url: &str, operation: F) -> Res
 where
     F: Fn(tokio_postgres::Client) -> Fut + Send + Sync,
     Fut: std::future::Future<Output = Result<u64, tokio_postgres::Error>> + Send,
+{
+    query_with_retry(database_url, operation).await.map(|_| ())
+}
+
+/// Run a database operation returning a value with automatic retry logic
+///
+/// Same as `execute_with_retry`, but passes on the result of the operation,
+/// e.g. rows returned by a query.
+///
+/// # Arguments
+/// * `database_url` - PostgreSQL connection URL with SSL parameters
+/// * `operation` - Async closure that performs the database operation
+///
+/// # Returns
+/// Result of the operation, or error after all retries exhausted
+pub async fn query_with_retry<F, Fut, T>(database_url: &str, operation: F) -> Result<T, String>
+where
+    F: Fn(tokio_postgres::Client) -> Fut + Send + Sync,
+    Fut: std::future::Future<Output = Result<T, tokio_postgres::Error>> + Send,
 {
     const MAX_RETRIES: usize = 100;
     const WAIT_BETWEEN_RETRIES: u64 = 5;
@@ -117,7 +136,7 @@ where
 
                 // Execute the provided operation
                 match operation(client).await {
-                    Ok(_) => return Ok(()),
+                    Ok(result) => return Ok(result),
                     Err(e) => error!("Query error: {}", e),
                 }
             }
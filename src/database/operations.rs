@@ -1,10 +1,15 @@
-/// Database operations for storing sensor and movement data
-use crate::database::connection::execute_with_retry;
-use crate::models::AverageData;
+/// Database operations for storing sensor, movement, battery, signal and air quality data
+use crate::database::connection::{execute_with_retry, query_with_retry};
+use crate::models::{AverageAirQualityData, AverageData, DiscoveredTag, HistoryRecord};
+use crate::utils::describe_reading;
+use time::OffsetDateTime;
 
 /// Store atmospheric sensor data (temperature, humidity, pressure) in database
 ///
-/// This function inserts averaged sensor readings into the sensor_data table.
+/// This function inserts averaged sensor readings into the sensor_data table,
+/// along with the minimum, maximum, standard deviation and median of each metric
+/// and the number of readings and of each metric's values rejected as outliers.
+/// Metrics without any valid samples in the interval are stored as NULL.
 /// It uses the retry mechanism to handle transient database connection issues.
 ///
 /// # Arguments
@@ -29,8 +34,12 @@ pub async fn store_sensor_data(
         async move {
             // Insert atmospheric data into sensor_data table
             client.execute(
-                "INSERT INTO sensor_data(sensor_mac, temperature, humidity, pressure, time, name, samples)
-                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                "INSERT INTO sensor_data(sensor_mac, temperature, humidity, pressure, time, name, samples, rejected, resolution, window_start, partial, coverage,
+                     temperature_min, temperature_max, temperature_stddev, temperature_median,
+                     humidity_min, humidity_max, humidity_stddev, humidity_median,
+                     pressure_min, pressure_max, pressure_stddev, pressure_median,
+                     temperature_rejected, humidity_rejected, pressure_rejected)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27)",
                 &[
                     &sensor_id,
                     &avg_data.temperature,
@@ -39,15 +48,131 @@ pub async fn store_sensor_data(
                     &avg_data.time,
                     &avg_data.name,
                     &avg_data.samples,
+                    &avg_data.rejected_atmospheric,
+                    &avg_data.resolution,
+                    &avg_data.window_start,
+                    &avg_data.partial,
+                    &avg_data.coverage,
+                    &avg_data.temperature_stats.map(|s| s.min),
+                    &avg_data.temperature_stats.map(|s| s.max),
+                    &avg_data.temperature_stats.map(|s| s.std_dev),
+                    &avg_data.temperature_stats.map(|s| s.median),
+                    &avg_data.humidity_stats.map(|s| s.min),
+                    &avg_data.humidity_stats.map(|s| s.max),
+                    &avg_data.humidity_stats.map(|s| s.std_dev),
+                    &avg_data.humidity_stats.map(|s| s.median),
+                    &avg_data.pressure_stats.map(|s| s.min),
+                    &avg_data.pressure_stats.map(|s| s.max),
+                    &avg_data.pressure_stats.map(|s| s.std_dev),
+                    &avg_data.pressure_stats.map(|s| s.median),
+                    &avg_data.rejected_values("temperature"),
+                    &avg_data.rejected_values("humidity"),
+                    &avg_data.rejected_values("pressure"),
                 ],
             ).await
         }
     }).await
 }
 
+/// Fetch the time of the latest atmospheric data stored for a sensor
+///
+/// # Arguments
+/// * `sensor_id` - MAC address of the sensor
+/// * `database_url` - PostgreSQL connection string
+///
+/// # Returns
+/// Time of the latest sensor_data row, None if nothing is stored for the sensor
+pub async fn fetch_last_sensor_time(
+    sensor_id: &str,
+    database_url: &str,
+) -> Result<Option<OffsetDateTime>, String> {
+    let sensor_id = sensor_id.to_string();
+
+    query_with_retry(database_url, move |client| {
+        let sensor_id = sensor_id.clone();
+        async move {
+            // Latest time of the sensor in sensor_data table
+            let row = client
+                .query_one(
+                    "SELECT max(time) FROM sensor_data WHERE sensor_mac = $1",
+                    &[&sensor_id],
+                )
+                .await?;
+            row.try_get::<_, Option<OffsetDateTime>>(0)
+        }
+    })
+    .await
+}
+
+/// Backfill atmospheric data downloaded from the on-tag history log
+///
+/// This function inserts every history log entry as its own row in the
+/// sensor_data table, with a sample count of 1.
+/// It uses the retry mechanism to handle transient database connection issues.
+/// The entries are inserted in a single transaction, so a retry after a
+/// failure partway through does not store any entry twice.
+///
+/// # Arguments
+/// * `sensor_id` - MAC address of the sensor
+/// * `name` - Human-readable sensor name
+/// * `records` - History log entries to store
+/// * `database_url` - PostgreSQL connection string
+///
+/// # Returns
+/// Result indicating success or failure
+pub async fn store_history_data(
+    sensor_id: &str,
+    name: &str,
+    records: &[HistoryRecord],
+    database_url: &str,
+) -> Result<(), String> {
+    // Clone data for move into async closure
+    let sensor_id = sensor_id.to_string();
+    let name = name.to_string();
+    let records = records.to_vec();
+
+    execute_with_retry(database_url, move |mut client| {
+        let sensor_id = sensor_id.clone();
+        let name = name.clone();
+        let records = records.clone();
+        async move {
+            // Insert history entries into sensor_data table, all or none
+            let transaction = client.transaction().await?;
+            let statement = transaction
+                .prepare(
+                    "INSERT INTO sensor_data(sensor_mac, temperature, humidity, pressure, time, name, samples)
+                     VALUES ($1, $2, $3, $4, $5, $6, 1)",
+                )
+                .await?;
+            let mut inserted = 0;
+            for record in &records {
+                inserted += transaction
+                    .execute(
+                        &statement,
+                        &[
+                            &sensor_id,
+                            &record.temperature,
+                            &record.humidity,
+                            &record.pressure,
+                            &record.time,
+                            &name,
+                        ],
+                    )
+                    .await?;
+            }
+            transaction.commit().await?;
+            Ok(inserted)
+        }
+    })
+    .await
+}
+
 /// Store movement sensor data (acceleration, movement counter) in database
 ///
-/// This function inserts averaged movement readings into the movement_data table.
+/// This function inserts averaged movement readings into the movement_data table,
+/// along with the minimum, maximum, standard deviation and median of each acceleration axis
+/// and the number of readings rejected as outliers.
+/// Metrics without any valid samples in the interval are stored as NULL.
 /// It uses the retry mechanism to handle transient database connection issues.
 ///
 /// # Arguments
@@ -72,17 +197,239 @@ pub async fn store_movement_data(
         async move {
             // Insert movement data into movement_data table
             client.execute(
-                "INSERT INTO movement_data(sensor_mac, acceleration_x, acceleration_y, acceleration_z, movement_counter, time, name, samples)
-                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                "INSERT INTO movement_data(sensor_mac, acceleration_x, acceleration_y, acceleration_z, movement_counter, time, name, samples, rejected, resolution, window_start, partial, coverage,
+                     acceleration_x_min, acceleration_x_max, acceleration_x_stddev, acceleration_x_median,
+                     acceleration_y_min, acceleration_y_max, acceleration_y_stddev, acceleration_y_median,
+                     acceleration_z_min, acceleration_z_max, acceleration_z_stddev, acceleration_z_median,
+                     acceleration_x_rejected, acceleration_y_rejected, acceleration_z_rejected)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28)",
                 &[
                     &sensor_id,
                     &avg_data.acceleration_x,
                     &avg_data.acceleration_y,
                     &avg_data.acceleration_z,
-                    &(avg_data.movement_counter as i32),
+                    &avg_data.movement_counter.map(|m| m as i32),
+                    &avg_data.time,
+                    &avg_data.name,
+                    &avg_data.samples,
+                    &avg_data.rejected_movement,
+                    &avg_data.resolution,
+                    &avg_data.window_start,
+                    &avg_data.partial,
+                    &avg_data.coverage,
+                    &avg_data.acceleration_x_stats.map(|s| s.min),
+                    &avg_data.acceleration_x_stats.map(|s| s.max),
+                    &avg_data.acceleration_x_stats.map(|s| s.std_dev),
+                    &avg_data.acceleration_x_stats.map(|s| s.median),
+                    &avg_data.acceleration_y_stats.map(|s| s.min),
+                    &avg_data.acceleration_y_stats.map(|s| s.max),
+                    &avg_data.acceleration_y_stats.map(|s| s.std_dev),
+                    &avg_data.acceleration_y_stats.map(|s| s.median),
+                    &avg_data.acceleration_z_stats.map(|s| s.min),
+                    &avg_data.acceleration_z_stats.map(|s| s.max),
+                    &avg_data.acceleration_z_stats.map(|s| s.std_dev),
+                    &avg_data.acceleration_z_stats.map(|s| s.median),
+                    &avg_data.rejected_values("acceleration_x"),
+                    &avg_data.rejected_values("acceleration_y"),
+                    &avg_data.rejected_values("acceleration_z"),
+                ],
+            ).await
+        }
+    }).await
+}
+
+/// Store battery and radio data (battery voltage, TX power) in database
+///
+/// This function inserts per-interval battery readings into the battery_data table
+/// so that battery decay can be charted per sensor.
+/// It uses the retry mechanism to handle transient database connection issues.
+///
+/// # Arguments
+/// * `sensor_id` - MAC address of the sensor
+/// * `avg_data` - Averaged data to store
+/// * `database_url` - PostgreSQL connection string
+///
+/// # Returns
+/// Result indicating success or failure
+pub async fn store_battery_data(
+    sensor_id: &str,
+    avg_data: &AverageData,
+    database_url: &str,
+) -> Result<(), String> {
+    // Clone data for move into async closure
+    let sensor_id = sensor_id.to_string();
+    let avg_data = avg_data.clone();
+
+    execute_with_retry(database_url, move |client| {
+        let sensor_id = sensor_id.clone();
+        let avg_data = avg_data.clone();
+        async move {
+            // Insert battery data into battery_data table
+            client.execute(
+                "INSERT INTO battery_data(sensor_mac, battery_voltage, tx_power, time, name, samples, resolution, window_start, partial, coverage)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                &[
+                    &sensor_id,
+                    &avg_data.battery_voltage.map(|b| b as i32),
+                    &avg_data.tx_power.map(|t| t as i16),
+                    &avg_data.time,
+                    &avg_data.name,
+                    &avg_data.samples,
+                    &avg_data.resolution,
+                    &avg_data.window_start,
+                    &avg_data.partial,
+                    &avg_data.coverage,
+                ],
+            ).await
+        }
+    }).await
+}
+
+/// Store radio signal data (minimum, average and maximum RSSI) in database
+///
+/// This function inserts per-interval signal strength statistics into the
+/// signal_data table to help tune gateway placement.
+/// It uses the retry mechanism to handle transient database connection issues.
+///
+/// # Arguments
+/// * `sensor_id` - MAC address of the sensor
+/// * `avg_data` - Averaged data to store
+/// * `database_url` - PostgreSQL connection string
+///
+/// # Returns
+/// Result indicating success or failure
+pub async fn store_signal_data(
+    sensor_id: &str,
+    avg_data: &AverageData,
+    database_url: &str,
+) -> Result<(), String> {
+    // Clone data for move into async closure
+    let sensor_id = sensor_id.to_string();
+    let avg_data = avg_data.clone();
+
+    execute_with_retry(database_url, move |client| {
+        let sensor_id = sensor_id.clone();
+        let avg_data = avg_data.clone();
+        async move {
+            // Insert signal data into signal_data table
+            client.execute(
+                "INSERT INTO signal_data(sensor_mac, rssi_min, rssi_avg, rssi_max, time, name, samples, resolution, window_start, partial, coverage)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                &[
+                    &sensor_id,
+                    &avg_data.rssi_min,
+                    &avg_data.rssi_avg,
+                    &avg_data.rssi_max,
                     &avg_data.time,
                     &avg_data.name,
                     &avg_data.samples,
+                    &avg_data.resolution,
+                    &avg_data.window_start,
+                    &avg_data.partial,
+                    &avg_data.coverage,
+                ],
+            ).await
+        }
+    }).await
+}
+
+/// Store air quality data (particulate matter, CO2, VOC, NOx, luminosity) in database
+///
+/// This function inserts averaged Ruuvi Air readings into the air_quality_data table.
+/// It uses the retry mechanism to handle transient database connection issues.
+///
+/// # Arguments
+/// * `sensor_id` - MAC address of the sensor
+/// * `avg_data` - Averaged air quality data to store
+/// * `database_url` - PostgreSQL connection string
+///
+/// # Returns
+/// Result indicating success or failure
+pub async fn store_air_quality_data(
+    sensor_id: &str,
+    avg_data: &AverageAirQualityData,
+    database_url: &str,
+) -> Result<(), String> {
+    // Clone data for move into async closure
+    let sensor_id = sensor_id.to_string();
+    let avg_data = avg_data.clone();
+
+    execute_with_retry(database_url, move |client| {
+        let sensor_id = sensor_id.clone();
+        let avg_data = avg_data.clone();
+        async move {
+            // Insert air quality data into air_quality_data table
+            client.execute(
+                "INSERT INTO air_quality_data(sensor_mac, temperature, humidity, pressure, pm1_0, pm2_5, pm4_0, pm10_0, co2, voc_index, nox_index, luminosity, time, name, samples, resolution, window_start, partial, coverage)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)",
+                &[
+                    &sensor_id,
+                    &avg_data.temperature,
+                    &avg_data.humidity,
+                    &avg_data.pressure,
+                    &avg_data.pm1_0,
+                    &avg_data.pm2_5,
+                    &avg_data.pm4_0,
+                    &avg_data.pm10_0,
+                    &avg_data.co2,
+                    &avg_data.voc_index,
+                    &avg_data.nox_index,
+                    &avg_data.luminosity,
+                    &avg_data.time,
+                    &avg_data.name,
+                    &avg_data.samples,
+                    &avg_data.resolution,
+                    &avg_data.window_start,
+                    &avg_data.partial,
+                    &avg_data.coverage,
+                ],
+            ).await
+        }
+    }).await
+}
+
+/// Record an unconfigured Ruuvi device in the discovered_tags table
+///
+/// Inserts the device on first sighting and otherwise updates its latest
+/// signal strength, data format and sample, keeping the time it was first seen
+/// and adding to its advertisement count.
+/// It uses the retry mechanism to handle transient database connection issues.
+///
+/// # Arguments
+/// * `tag` - Discovered device with its latest advertisement
+/// * `database_url` - PostgreSQL connection string
+///
+/// # Returns
+/// Result indicating success or failure
+pub async fn store_discovered_tag(tag: &DiscoveredTag, database_url: &str) -> Result<(), String> {
+    // Clone data for move into async closure
+    let tag = tag.clone();
+    let data_format = tag.data_format as i16;
+    let sample = tag.sample.as_ref().map(describe_reading);
+
+    execute_with_retry(database_url, move |client| {
+        let tag = tag.clone();
+        let sample = sample.clone();
+        async move {
+            // Insert or update the device in discovered_tags table
+            client.execute(
+                "INSERT INTO discovered_tags(sensor_mac, data_format, rssi, adapter, sample, advertisements, first_seen, last_seen)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $7)
+                 ON CONFLICT (sensor_mac) DO UPDATE SET
+                     data_format = EXCLUDED.data_format,
+                     rssi = EXCLUDED.rssi,
+                     adapter = EXCLUDED.adapter,
+                     sample = EXCLUDED.sample,
+                     advertisements = discovered_tags.advertisements + EXCLUDED.advertisements,
+                     last_seen = EXCLUDED.last_seen",
+                &[
+                    &tag.mac,
+                    &data_format,
+                    &tag.rssi,
+                    &tag.adapter,
+                    &sample,
+                    &tag.advertisements,
+                    &tag.last_seen,
                 ],
             ).await
         }
@@ -0,0 +1,331 @@
+/// Backfilling sensor_data from the on-tag history logs
+use log::{error, info, warn};
+use time::{Duration, OffsetDateTime};
+use tokio::time::sleep;
+
+use crate::bluetooth::history::{download_history, GattPeer, NusConnection};
+use crate::config::SensorConfig;
+use crate::sink::HistoryStore;
+
+// The sync starts together with the scanners, before BlueZ has discovered the tags
+const HISTORY_SYNC_ATTEMPTS: u32 = 5; // Attempts per tag before giving up
+const HISTORY_RETRY_DELAY_SECS: u64 = 30; // Delay before the first retry, doubled after each
+
+/// Backfill data missed while the service was down from each tag's history log
+///
+/// Tags are synced one at a time, since BlueZ handles concurrent connections
+/// poorly while scanning. Tags that fail are retried after the others with an
+/// increasing delay, giving the scanners time to discover them. A tag that
+/// still cannot be reached is skipped; the next sync picks up its history as
+/// long as the tag still has it.
+///
+/// # Arguments
+/// * `peer` - GATT peer used to connect to the tags
+/// * `store` - Destination of the downloaded history entries
+/// * `config` - Configuration containing the tags and history settings
+pub async fn sync_history<P: GattPeer, S: HistoryStore>(
+    peer: &P,
+    store: &S,
+    config: &SensorConfig,
+) {
+    let mut tags: Vec<_> = config
+        .tags
+        .iter()
+        .map(|(mac, name)| (mac.as_str(), name.as_str()))
+        .collect();
+    tags.sort();
+
+    sync_tags(peer, store, tags, config.history_max_days).await;
+}
+
+/// Sync the given tags, retrying the failed ones with an increasing delay
+///
+/// # Arguments
+/// * `tags` - MAC addresses and names of the tags to sync
+/// * `history_max_days` - Maximum age of the entries to download
+async fn sync_tags<P: GattPeer, S: HistoryStore>(
+    peer: &P,
+    store: &S,
+    mut tags: Vec<(&str, &str)>,
+    history_max_days: u32,
+) {
+    let mut delay_secs = HISTORY_RETRY_DELAY_SECS;
+    for attempt in 1..=HISTORY_SYNC_ATTEMPTS {
+        let mut failed = Vec::new();
+        for (mac, name) in tags {
+            let now = OffsetDateTime::now_utc();
+            match sync_tag(peer, store, mac, name, history_max_days, now).await {
+                Ok(0) => info!("No history to backfill for {} ({})", name, mac),
+                Ok(count) => info!(
+                    "Backfilled {} history entries for {} ({})",
+                    count, name, mac
+                ),
+                Err(e) if attempt < HISTORY_SYNC_ATTEMPTS => {
+                    warn!(
+                        "History sync failed for {} ({}), retrying in {} s: {}",
+                        name, mac, delay_secs, e
+                    );
+                    failed.push((mac, name));
+                }
+                Err(e) => warn!("History sync failed for {} ({}): {}", name, mac, e),
+            }
+        }
+        if failed.is_empty() {
+            break;
+        }
+
+        sleep(tokio::time::Duration::from_secs(delay_secs)).await;
+        delay_secs *= 2;
+        tags = failed;
+    }
+}
+
+/// Download and store the history of a single tag since its latest stored data
+///
+/// # Arguments
+/// * `history_max_days` - Maximum age of the entries to download
+/// * `now` - Current time
+///
+/// # Returns
+/// Number of history entries stored
+async fn sync_tag<P: GattPeer, S: HistoryStore>(
+    peer: &P,
+    store: &S,
+    mac: &str,
+    name: &str,
+    history_max_days: u32,
+    now: OffsetDateTime,
+) -> Result<usize, String> {
+    let oldest = now - Duration::days(history_max_days as i64);
+    let since = match store.last_sensor_time(mac).await? {
+        Some(last) => last.max(oldest),
+        None => oldest,
+    };
+
+    let mut connection = peer.connect(mac).await.map_err(|e| e.to_string())?;
+    let result = download_history(&mut connection, since, now).await;
+    connection.disconnect().await;
+
+    // The tag may return the entry at the start time itself, which is already stored
+    let records: Vec<_> = result
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|record| record.time > since)
+        .collect();
+    if records.is_empty() {
+        return Ok(0);
+    }
+
+    if let Err(e) = store.store_history(mac, name, &records).await {
+        error!("Failed to store history data for {}: {}", mac, e);
+        return Err(e);
+    }
+    Ok(records.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::history::HistoryError;
+    use crate::models::HistoryRecord;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    const TAG_MAC: &str = "AA:BB:CC:DD:EE:01";
+    const NOW: i64 = 1_704_111_000; // 2024-01-01 12:10 UTC
+
+    /// Tag answering the log read command from its logged temperatures
+    ///
+    /// Like the firmware, it returns every entry logged at or after the
+    /// requested time, followed by the end marker.
+    #[derive(Default)]
+    struct MockTag {
+        log: Vec<i64>,
+        requested: Arc<Mutex<Vec<i64>>>,
+        /// Number of connection attempts failing before the tag is discovered
+        undiscovered_attempts: Mutex<u32>,
+    }
+
+    struct MockConnection {
+        log: Vec<i64>,
+        requested: Arc<Mutex<Vec<i64>>>,
+        notifications: VecDeque<Vec<u8>>,
+    }
+
+    impl GattPeer for MockTag {
+        type Connection = MockConnection;
+
+        async fn connect(&self, _: &str) -> Result<MockConnection, HistoryError> {
+            let mut undiscovered_attempts = self.undiscovered_attempts.lock().unwrap();
+            if *undiscovered_attempts > 0 {
+                *undiscovered_attempts -= 1;
+                return Err(HistoryError::Connection("Device not found".to_string()));
+            }
+            Ok(MockConnection {
+                log: self.log.clone(),
+                requested: self.requested.clone(),
+                notifications: VecDeque::new(),
+            })
+        }
+    }
+
+    impl NusConnection for MockConnection {
+        async fn write(&mut self, data: &[u8]) -> Result<(), HistoryError> {
+            let since = u32::from_be_bytes([data[7], data[8], data[9], data[10]]) as i64;
+            self.requested.lock().unwrap().push(since);
+            for &timestamp in self.log.iter().filter(|&&timestamp| timestamp >= since) {
+                // Temperature value of 21.50°C
+                let mut message = vec![0x30, 0x30, 0x10];
+                message.extend_from_slice(&(timestamp as u32).to_be_bytes());
+                message.extend_from_slice(&2150i32.to_be_bytes());
+                self.notifications.push_back(message);
+            }
+            let mut end_marker = vec![0x3A, 0x3A, 0x10];
+            end_marker.extend_from_slice(&[0xFF; 8]);
+            self.notifications.push_back(end_marker);
+            Ok(())
+        }
+
+        async fn next_notification(&mut self) -> Option<Vec<u8>> {
+            self.notifications.pop_front()
+        }
+
+        async fn disconnect(self) {}
+    }
+
+    /// Store recording every batch of history entries it is given
+    #[derive(Default)]
+    struct RecordingStore {
+        last: Option<i64>,
+        stored: Mutex<Vec<Vec<HistoryRecord>>>,
+    }
+
+    impl HistoryStore for RecordingStore {
+        async fn last_sensor_time(&self, _: &str) -> Result<Option<OffsetDateTime>, String> {
+            Ok(self.last.map(time))
+        }
+
+        async fn store_history(
+            &self,
+            _: &str,
+            _: &str,
+            records: &[HistoryRecord],
+        ) -> Result<(), String> {
+            self.stored.lock().unwrap().push(records.to_vec());
+            Ok(())
+        }
+    }
+
+    fn time(timestamp: i64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(timestamp).unwrap()
+    }
+
+    fn stored_times(store: &RecordingStore) -> Vec<Vec<i64>> {
+        store
+            .stored
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|batch| batch.iter().map(|r| r.time.unix_timestamp()).collect())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn syncs_since_latest_stored_data() {
+        let tag = MockTag {
+            log: vec![NOW - 7200, NOW - 3600, NOW - 1800, NOW - 600],
+            ..Default::default()
+        };
+        let store = RecordingStore {
+            last: Some(NOW - 3600),
+            ..Default::default()
+        };
+
+        let result = sync_tag(&tag, &store, TAG_MAC, "Test", 10, time(NOW)).await;
+
+        assert_eq!(result, Ok(2));
+        assert_eq!(*tag.requested.lock().unwrap(), vec![NOW - 3600]);
+        // The entry at the latest stored time is not stored again
+        assert_eq!(stored_times(&store), vec![vec![NOW - 1800, NOW - 600]]);
+    }
+
+    #[tokio::test]
+    async fn limits_sync_to_history_max_days() {
+        let day = 86_400;
+        let tag = MockTag {
+            log: vec![NOW - 20 * day, NOW - 5 * day],
+            ..Default::default()
+        };
+        let never_synced = RecordingStore::default();
+        let long_outage = RecordingStore {
+            last: Some(NOW - 30 * day),
+            ..Default::default()
+        };
+
+        let first = sync_tag(&tag, &never_synced, TAG_MAC, "Test", 10, time(NOW)).await;
+        let second = sync_tag(&tag, &long_outage, TAG_MAC, "Test", 10, time(NOW)).await;
+
+        assert_eq!(first, Ok(1));
+        assert_eq!(second, Ok(1));
+        assert_eq!(
+            *tag.requested.lock().unwrap(),
+            vec![NOW - 10 * day, NOW - 10 * day]
+        );
+        assert_eq!(stored_times(&never_synced), vec![vec![NOW - 5 * day]]);
+        assert_eq!(stored_times(&long_outage), vec![vec![NOW - 5 * day]]);
+    }
+
+    #[tokio::test]
+    async fn skips_storing_when_nothing_is_new() {
+        let tag = MockTag {
+            log: vec![NOW - 1200, NOW - 600],
+            ..Default::default()
+        };
+        let store = RecordingStore {
+            last: Some(NOW - 600),
+            ..Default::default()
+        };
+
+        let result = sync_tag(&tag, &store, TAG_MAC, "Test", 10, time(NOW)).await;
+
+        assert_eq!(result, Ok(0));
+        assert!(store.stored.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_tags_not_yet_discovered() {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let tag = MockTag {
+            log: vec![now - 600],
+            undiscovered_attempts: Mutex::new(2),
+            ..Default::default()
+        };
+        let store = RecordingStore::default();
+        let start = tokio::time::Instant::now();
+
+        sync_tags(&tag, &store, vec![(TAG_MAC, "Test")], 10).await;
+
+        // Retried after 30 s and 60 s
+        assert_eq!(start.elapsed().as_secs(), 90);
+        assert_eq!(stored_times(&store), vec![vec![now - 600]]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_on_unreachable_tags() {
+        let tag = MockTag {
+            undiscovered_attempts: Mutex::new(u32::MAX),
+            ..Default::default()
+        };
+        let store = RecordingStore::default();
+        let start = tokio::time::Instant::now();
+
+        sync_tags(&tag, &store, vec![(TAG_MAC, "Test")], 10).await;
+
+        assert_eq!(start.elapsed().as_secs(), 30 + 60 + 120 + 240);
+        assert_eq!(
+            *tag.undiscovered_attempts.lock().unwrap(),
+            u32::MAX - HISTORY_SYNC_ATTEMPTS
+        );
+        assert!(store.stored.lock().unwrap().is_empty());
+    }
+}
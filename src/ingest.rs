@@ -0,0 +1,163 @@
+/// Turning received advertisements into sensor readings
+use log::{debug, warn};
+use std::collections::HashSet;
+
+use crate::bluetooth::scanner::{decode_advertisement, decode_mac, DecodeError};
+use crate::config::{DiscoveryMode, SensorConfig};
+use crate::models::{Advertisement, DiscoveredTag, SensorReading};
+use crate::utils::SequenceTracker;
+
+/// Outcome of processing a single advertisement
+///
+/// Every variant except `Discovered` and `Unconfigured` carries the sensor ID
+/// the advertisement was attributed to.
+#[derive(Debug)]
+pub enum Processed {
+    /// A new measurement from a configured sensor
+    Reading(String, SensorReading),
+    /// A repeat of a recent measurement from the sensor
+    Duplicate(String),
+    /// An advertisement from a configured sensor that could not be decoded
+    Failed(String, DecodeError),
+    /// An advertisement from a sensor that is not configured, in discovery mode
+    Discovered(DiscoveredTag),
+    /// An advertisement from a sensor that is not configured
+    Unconfigured,
+}
+
+/// Resolves, decodes and deduplicates advertisements from all sources
+///
+/// Lives for the whole run of the service so that sequence numbers are
+/// tracked across collection intervals and address mismatches are only
+/// reported once.
+#[derive(Debug)]
+pub struct AdvertisementProcessor {
+    sequence_tracker: SequenceTracker,
+    reported_mismatches: HashSet<(String, String)>,
+}
+
+impl AdvertisementProcessor {
+    /// Create a processor with no history
+    pub fn new() -> Self {
+        Self {
+            sequence_tracker: SequenceTracker::new(),
+            reported_mismatches: HashSet::new(),
+        }
+    }
+
+    /// Process a single advertisement
+    ///
+    /// Sensors are identified by the MAC address embedded in the payload when
+    /// available, falling back to the BLE address for formats without one.
+    ///
+    /// # Arguments
+    /// * `advertisement` - Received advertisement
+    /// * `config` - Configuration containing sensor MAC addresses and encryption keys
+    ///
+    /// # Returns
+    /// What the advertisement turned out to be
+    pub fn process(&mut self, advertisement: &Advertisement, config: &SensorConfig) -> Processed {
+        let sensor_id = self.resolve_sensor_id(advertisement);
+
+        // Only process devices that are in our configuration
+        if !config.tags.contains_key(&sensor_id) {
+            if config.discovery_mode == DiscoveryMode::Off {
+                return Processed::Unconfigured;
+            }
+            return Processed::Discovered(discover(sensor_id, advertisement, config));
+        }
+
+        // Decode the RuuviTag or Ruuvi Air data
+        let mut reading = match decode_advertisement(
+            &advertisement.payload,
+            config.encryption_keys.get(&sensor_id),
+        ) {
+            Ok(SensorReading::Tag(mut sensor_data)) => {
+                // Signal strength is not part of the payload, take it from the receiver
+                sensor_data.rssi = advertisement.rssi;
+                SensorReading::Tag(sensor_data)
+            }
+            Ok(reading) => reading,
+            Err(e) => {
+                debug!("Failed to decode data from {}: {}", sensor_id, e);
+                return Processed::Failed(sensor_id, e);
+            }
+        };
+
+        reading.set_adapter(&advertisement.adapter);
+        reading.set_received_at(advertisement.received_at);
+
+        // Skip advertisements that repeat a recent measurement, including
+        // copies of the same measurement heard by another adapter
+        if !self.sequence_tracker.is_new(&sensor_id, &reading) {
+            debug!("Dropping duplicate advertisement from {}", sensor_id);
+            return Processed::Duplicate(sensor_id);
+        }
+
+        match &reading {
+            SensorReading::Tag(data) => debug!(
+                "Received data from {} via {}: temp={:?}°C, humidity={:?}%, pressure={:?} hPa, rssi={:?} dBm",
+                sensor_id, advertisement.adapter, data.temperature, data.humidity, data.pressure, data.rssi
+            ),
+            SensorReading::AirQuality(data) => debug!(
+                "Received air quality data from {} via {}: co2={:?} ppm, pm2.5={:?} µg/m³",
+                sensor_id, advertisement.adapter, data.co2, data.pm2_5
+            ),
+        }
+
+        Processed::Reading(sensor_id, reading)
+    }
+
+    /// Determine the sensor ID of an advertisement
+    ///
+    /// Prefers the MAC embedded in the payload, since the BLE address may be a
+    /// random private address depending on adapter and firmware.
+    fn resolve_sensor_id(&mut self, advertisement: &Advertisement) -> String {
+        let Some(mac) = decode_mac(&advertisement.payload) else {
+            return advertisement.address.clone();
+        };
+
+        if mac != advertisement.address
+            && self
+                .reported_mismatches
+                .insert((mac.clone(), advertisement.address.clone()))
+        {
+            if advertisement.random_address {
+                debug!(
+                    "Tag {} advertises from random address {}",
+                    mac, advertisement.address
+                );
+            } else {
+                warn!(
+                    "MAC address {} in payload does not match BLE address {}",
+                    mac, advertisement.address
+                );
+            }
+        }
+
+        mac
+    }
+}
+
+/// Describe an advertisement from an unconfigured device for discovery mode
+fn discover(mac: String, advertisement: &Advertisement, config: &SensorConfig) -> DiscoveredTag {
+    let sample = decode_advertisement(&advertisement.payload, config.encryption_keys.get(&mac))
+        .ok()
+        .map(|mut reading| {
+            if let SensorReading::Tag(data) = &mut reading {
+                data.rssi = advertisement.rssi;
+            }
+            reading.set_adapter(&advertisement.adapter);
+            reading
+        });
+
+    DiscoveredTag {
+        mac,
+        data_format: advertisement.payload.first().copied().unwrap_or_default(),
+        rssi: advertisement.rssi,
+        adapter: advertisement.adapter.clone(),
+        sample,
+        advertisements: 1,
+        last_seen: advertisement.received_at,
+    }
+}
@@ -5,18 +5,39 @@
 // This RuuviTag sensor data collection system implements an ETL pipeline:
 //
 // 1. EXTRACT (Bluetooth Module):
-//    - Scans for RuuviTag sensors via BLE advertisements
-//    - Collects readings over 30-minute intervals
-//    - Decodes manufacturer data using RuuviTag format 5 protocol
+//    - Continuously scans for RuuviTag sensors via BLE advertisements
+//    - Streams every received advertisement to the aggregation loop
+//    - Listens on one or more Bluetooth adapters and merges what they hear
+//    - Recovers failed adapters with backoff, session re-acquisition and power-cycling
+//    - Reads advertisements through a pluggable source (BlueZ scanner or simulated tags)
+//    - Optionally captures every raw advertisement to rotating JSON Lines files
+//    - Replays captured advertisements with their original timestamps (accelerated)
+//    - Accepts advertisements relayed by Ruuvi Gateways over HTTP or MQTT
+//    - Downloads on-tag history logs over GATT to backfill outages (history sync)
+//    - Collects readings over one or more aggregation windows (default 30 minutes)
+//    - Aligns windows to wall-clock boundaries (e.g. :00 and :30) in a configured time zone
+//    - Decodes manufacturer data using RuuviTag format 3 and 5 protocols
+//    - Decrypts RuuviTag format 8 payloads with per-tag AES-128 keys
+//    - Decodes Ruuvi Air formats 6 and E1 (particulate matter, CO2, VOC, NOx)
 //    - Handles multiple sensors configured via environment variables
+//    - Identifies sensors by the MAC embedded in the payload (random BLE addresses)
 //
 // 2. TRANSFORM (Utils Module):
-//    - Calculates averages for all sensor metrics
+//    - Calculates time-weighted averages for all sensor metrics (half the gap to each neighbour)
+//    - Records the fraction of each window covered by readings
+//    - Calculates min, max, standard deviation and median of atmospheric and acceleration metrics
 //    - Handles movement counter deltas and data validation
+//    - Optionally rejects values outside sensor ranges and Hampel (MAD) outliers before averaging
+//    - Drops stale cached advertisements using measurement sequence numbers
 //
 // 3. LOAD (Database Module):
 //    - Stores atmospheric data (temp, humidity, pressure) in sensor_data table
 //    - Stores movement data (acceleration, movement counter) in movement_data table
+//    - Stores battery data (battery voltage, TX power) in battery_data table
+//    - Stores signal strength statistics (RSSI) in signal_data table
+//    - Stores Ruuvi Air readings in air_quality_data table
+//    - Records unconfigured Ruuvi devices in discovered_tags table (discovery mode)
+//    - Writes aggregates through a pluggable sink, so the pipeline can be tested without a database
 //    - Implements robust retry logic for transient connection failures
 //    - Supports SSL/TLS connections with custom CA certificates
 //
@@ -30,156 +51,487 @@
 // Configuration:
 // - RUUVI_TAGS: Comma-separated "MAC=Name" pairs for sensor configuration
 // - DATABASE_URL: PostgreSQL connection string with SSL parameters
+// - RUUVI_TAG_KEYS: Optional comma-separated "MAC=HexKey" pairs for encrypted tags
+// - BLUETOOTH_ADAPTERS: Optional comma-separated adapter names (default adapter if unset)
+// - DISCOVERY_MODE: Optional "off", "log" or "store" for reporting unconfigured tags
+// - READING_SOURCE: Optional "bluetooth" (default) or "simulated" advertisement source
+// - CAPTURE_FILE: Optional JSON Lines file for raw advertisements (CAPTURE_MAX_BYTES/_FILES)
+// - REPLAY_FILE/REPLAY_SPEED: Capture file and acceleration for READING_SOURCE=replay
+// - GATEWAY_LISTEN: HTTP server address for READING_SOURCE=gateway (default 127.0.0.1:8080)
+// - GATEWAY_TOKEN: Optional bearer token Ruuvi Gateways must send
+// - MQTT_URL/MQTT_TOPIC: Broker and topic filter for READING_SOURCE=mqtt (default ruuvi/#)
+// - AGGREGATION_WINDOWS: Optional comma-separated window lengths, e.g. "1m,30m,1d" (default 30m)
+// - WINDOW_TIME_ZONE: Optional IANA time zone windows are aligned in, e.g. "Europe/Helsinki"
+// - WINDOW_UTC_OFFSET: Optional fixed UTC offset instead, e.g. "+02:00" (default UTC)
+// - OUTLIER_FILTER/HAMPEL_THRESHOLD: Optional outlier rejection settings (default off, 3.0)
+// - HISTORY_SYNC/HISTORY_MAX_DAYS: Optional backfill from tag history logs at startup
 // - Optional .env file support for development
 //
 // ================================================================
 mod bluetooth;
+mod clock;
 mod config;
 mod database;
+mod history;
+mod ingest;
 mod models;
+mod sink;
+mod source;
 mod utils;
+mod window;
 
-use log::{error, info, warn};
-use std::collections::HashMap;
-use time::OffsetDateTime;
-use tokio::time::{sleep, Duration};
+use log::{debug, error, info, warn};
+use tokio::sync::mpsc;
 
-use bluetooth::scanner::scan_for_ruuvitags;
-use config::SensorConfig;
-use database::operations::{store_movement_data, store_sensor_data};
-use models::{AverageData, RuuviData};
-use utils::{calculate_averages, duration_to_seconds, format_datetime};
-
-// Configuration constants for data collection timing
-const COLLECTION_INTERVAL_SECS: u64 = 1800; // 30 minutes
-const POLL_INTERVAL_SECS: u64 = 30;
-const SCAN_DURATION_SECS: u64 = 20;
+use bluetooth::history::BluerGattPeer;
+use clock::{Clock, SystemClock};
+use config::{DiscoveryMode, SensorConfig, SourceKind};
+use history::sync_history;
+use ingest::{AdvertisementProcessor, Processed};
+use models::{Advertisement, AverageData, DiscoveredTag, WindowBounds};
+use sink::{run_storage, AggregateSink, PostgresSink, WindowAggregates};
+use source::{
+    BluetoothSource, CaptureWriter, CapturingSource, GatewaySource, MqttSource, ReadingSource,
+    ReplaySource, SimulatedSource,
+};
+use utils::{
+    calculate_air_quality_averages, calculate_averages, count_adapters, describe_reading,
+    format_counts, format_datetime, format_optional, format_resolution, format_stats,
+};
+use window::{CollectionWindow, ReadingBuffer};
 
 /// Main application loop that continuously collects sensor data
 ///
 /// This function implements the core ETL (Extract, Transform, Load) process:
-/// 1. Extract: Scan for RuuviTag sensors over Bluetooth
-/// 2. Transform: Calculate averages over collection intervals
-/// 3. Load: Store processed data in PostgreSQL database
+/// 1. Extract: Receive RuuviTag advertisements from the reading source
+/// 2. Transform: Calculate averages over each configured aggregation window
+/// 3. Load: Store processed data in the sink (the PostgreSQL database)
 ///
-/// The loop runs indefinitely, collecting data in 30-minute intervals.
-async fn main_loop(config: SensorConfig) -> Result<(), Box<dyn std::error::Error>> {
+/// All windows (30 minutes by default) aggregate the same stream of readings
+/// and are stored independently as their deadlines pass, until the source is
+/// exhausted, which for the Bluetooth scanner means indefinitely. Readings are
+/// assigned to windows by the time they were received, so replayed data keeps
+/// its original timing and advertisements queued at a deadline end up in the
+/// window they were heard in. Aggregates are stored by a separate task, so
+/// that a slow database never delays collection.
+async fn main_loop<S, C, K>(
+    config: SensorConfig,
+    mut source: S,
+    clock: C,
+    sink: K,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: ReadingSource,
+    C: Clock,
+    K: AggregateSink + Send + Sync + 'static,
+{
     info!("Starting RuuviTag data collection service");
 
-    loop {
-        // HashMap to store all measurements during the collection interval
-        // Key: sensor MAC address, Value: vector of all readings from that sensor
-        let mut measurements: HashMap<String, Vec<RuuviData>> = HashMap::new();
-        let start_time = OffsetDateTime::now_utc();
+    let (store_queue, stored) = mpsc::unbounded_channel();
+    let storage = tokio::spawn(run_storage(sink, stored));
+
+    // Advertisement received after the end of the previous window
+    let mut carried_over: Option<Advertisement> = None;
+
+    // Sequence numbers are tracked across windows so that a cached advertisement
+    // from a tag that went out of range is never counted twice
+    let mut processor = AdvertisementProcessor::new();
+
+    // Readings are kept once for all windows until the longest one is finished
+    let mut buffer = ReadingBuffer::new();
 
+    let start_time = clock.now();
+    let mut windows: Vec<CollectionWindow> = config
+        .aggregation_windows
+        .iter()
+        .map(|&resolution| CollectionWindow::new(resolution, config.window_time_zone, start_time))
+        .collect();
+    for window in windows.iter() {
         info!(
-            "Starting collection interval at: {}",
-            format_datetime(&start_time)
+            "Starting {} collection window at: {}{}",
+            format_resolution(window.resolution),
+            format_datetime(&window.time_zone.to_local(window.start_time)),
+            if window.partial { " (partial)" } else { "" }
         );
+    }
 
-        // Data collection phase - gather readings for COLLECTION_INTERVAL_SECS
+    loop {
+        // Data collection phase - gather readings until the earliest window deadline
+        let deadline = windows
+            .iter()
+            .map(|window| window.deadline)
+            .min()
+            .unwrap_or(start_time);
+        let mut exhausted = false;
         loop {
-            let elapsed = duration_to_seconds(OffsetDateTime::now_utc() - start_time);
-            if elapsed >= COLLECTION_INTERVAL_SECS {
-                break;
-            }
-
-            // Perform a single scan for all configured RuuviTags
-            let current_data = match scan_for_ruuvitags(&config).await {
-                Ok(data) => data,
-                Err(e) => {
-                    error!("Scan failed: {}", e);
-                    continue; // Skip this scan iteration but continue collecting
-                }
+            let advertisement = match carried_over.take() {
+                Some(advertisement) => advertisement,
+                None => tokio::select! {
+                    // Drain advertisements that queued up before the deadline first
+                    biased;
+                    advertisement = source.next_advertisement() => match advertisement {
+                        Some(advertisement) => advertisement,
+                        None => {
+                            exhausted = true;
+                            break;
+                        }
+                    },
+                    _ = clock.sleep_until(deadline) => break,
+                },
             };
 
-            // Accumulate data from this scan into our measurements collection
-            for (sensor_id, sensor_data) in current_data {
-                measurements
-                    .entry(sensor_id)
-                    .or_insert_with(Vec::new)
-                    .push(sensor_data);
+            // An advertisement received past the deadline belongs to the next window
+            if advertisement.received_at >= deadline {
+                carried_over = Some(advertisement);
+                break;
             }
 
-            // Calculate how much time is left in the collection interval
-            let time_elapsed = duration_to_seconds(OffsetDateTime::now_utc() - start_time);
-            let time_remaining = COLLECTION_INTERVAL_SECS.saturating_sub(time_elapsed);
+            // Advertisements relayed late may belong to windows that were already stored
+            let received_at = advertisement.received_at;
+            if !windows.iter().any(|window| window.contains(received_at)) {
+                debug!(
+                    "Ignoring advertisement from {} received at {} before the open windows",
+                    advertisement.address,
+                    format_datetime(&received_at)
+                );
+                continue;
+            }
 
-            if time_remaining == 0 {
-                break;
+            // Count the advertisement in the windows it was received in, and keep
+            // its reading once for all of them
+            let processed = processor.process(&advertisement, &config);
+            for window in windows.iter_mut().filter(|w| w.contains(received_at)) {
+                window.record(&processed);
+            }
+            if let Processed::Reading(sensor_id, reading) = processed {
+                buffer.insert(sensor_id, reading);
             }
+        }
 
-            // Wait before next scan, accounting for scan duration
-            // Don't wait longer than the remaining collection time
-            let sleep_time = std::cmp::min(
-                POLL_INTERVAL_SECS.saturating_sub(SCAN_DURATION_SECS),
-                time_remaining,
-            );
+        // Aggregate and queue every window that is due, or all of them once the
+        // source is exhausted. An advertisement received past the deadline means
+        // the deadline has passed even if the clock lags behind its timestamp.
+        // The first window also reports discovered devices and source health, so
+        // that they are logged once per its interval.
+        let now = match carried_over {
+            Some(_) => clock.now().max(deadline),
+            None => clock.now(),
+        };
+        for (index, window) in windows.iter_mut().enumerate() {
+            if !exhausted && now < window.deadline {
+                continue;
+            }
+            let finished = std::mem::replace(window, window.next(now));
+            let bounds = finished.bounds(now);
+            let aggregates = finish_window(&config, &buffer, finished, &bounds, index == 0);
+            if store_queue.send(aggregates).is_err() {
+                error!("Storage task has stopped, aggregates are not stored");
+            }
+            if index == 0 {
+                // Log source health (e.g. Bluetooth adapters) for monitoring
+                source.log_health();
+            }
+            if !exhausted {
+                info!(
+                    "Starting {} collection window at: {}",
+                    format_resolution(window.resolution),
+                    format_datetime(&window.time_zone.to_local(window.start_time))
+                );
+            }
+        }
 
-            if sleep_time > 0 {
-                sleep(Duration::from_secs(sleep_time)).await;
+        if exhausted {
+            info!("Reading source exhausted, stopping data collection");
+            // Let the queued aggregates be stored before returning
+            drop(store_queue);
+            if let Err(e) = storage.await {
+                error!("Storage task failed: {}", e);
             }
+            return Ok(());
+        }
+
+        // Readings before the earliest open window are no longer needed
+        if let Some(oldest) = windows.iter().map(|window| window.start_time).min() {
+            buffer.prune(oldest);
         }
+    }
+}
+
+/// Aggregate and report the readings of a finished window
+///
+/// # Arguments
+/// * `config` - Configuration containing sensor names and discovery mode
+/// * `buffer` - Readings shared by all windows
+/// * `window` - Window whose readings are aggregated
+/// * `bounds` - Start and end the aggregates are stamped with
+/// * `report_discovered` - Whether to report the unconfigured devices heard during the window
+///
+/// # Returns
+/// Aggregates to store
+fn finish_window(
+    config: &SensorConfig,
+    buffer: &ReadingBuffer,
+    window: CollectionWindow,
+    bounds: &WindowBounds,
+    report_discovered: bool,
+) -> WindowAggregates {
+    let label = format_resolution(window.resolution);
+    info!(
+        "{} collection window complete at: {}{}",
+        label,
+        format_datetime(&window.time_zone.to_local(bounds.end)),
+        if bounds.partial { " (partial)" } else { "" }
+    );
+
+    let measurements = buffer.measurements(&window);
+    let air_measurements = buffer.air_measurements(&window);
+    let CollectionWindow {
+        duplicates,
+        decode_errors,
+        discovered,
+        ..
+    } = window;
+
+    // Data processing phase - calculate averages from all collected measurements
+    let sensor_averages = calculate_averages(&measurements, config, bounds);
+    let air_quality_averages = calculate_air_quality_averages(&air_measurements, config, bounds);
 
-        let end_time = OffsetDateTime::now_utc();
+    // Log summary of processed data for monitoring
+    for (sensor_id, avg_data) in sensor_averages.iter() {
+        info!("{} summary for {}:", label, avg_data.name);
+        info!(
+            "  Average temperature: {}°C ({})",
+            format_optional(avg_data.temperature, 2),
+            format_stats(avg_data.temperature_stats.as_ref(), 2)
+        );
+        info!(
+            "  Average humidity: {}% ({})",
+            format_optional(avg_data.humidity, 2),
+            format_stats(avg_data.humidity_stats.as_ref(), 2)
+        );
+        info!(
+            "  Average pressure: {} hPa ({})",
+            format_optional(avg_data.pressure, 2),
+            format_stats(avg_data.pressure_stats.as_ref(), 2)
+        );
+        info!(
+            "  Average acceleration X: {} g",
+            format_optional(avg_data.acceleration_x, 3)
+        );
+        info!(
+            "  Average acceleration Y: {} g",
+            format_optional(avg_data.acceleration_y, 3)
+        );
+        info!(
+            "  Average acceleration Z: {} g",
+            format_optional(avg_data.acceleration_z, 3)
+        );
+        info!(
+            "  Movement counter delta: {}",
+            format_optional(avg_data.movement_counter, 0)
+        );
+        info!(
+            "  Minimum battery voltage: {} mV",
+            format_optional(avg_data.battery_voltage, 0)
+        );
+        info!("  TX power: {} dBm", format_optional(avg_data.tx_power, 0));
+        info!(
+            "  RSSI min/avg/max: {}/{}/{} dBm",
+            format_optional(avg_data.rssi_min, 0),
+            format_optional(avg_data.rssi_avg, 1),
+            format_optional(avg_data.rssi_max, 0)
+        );
+        info!(
+            "  Based on {} samples covering {:.1}% of the window",
+            avg_data.samples,
+            avg_data.coverage * 100.0
+        );
+        info!(
+            "  Samples per adapter: {}",
+            format_counts(Some(&count_adapters(
+                measurements[sensor_id.as_str()]
+                    .iter()
+                    .map(|m| m.adapter.as_deref())
+            )))
+        );
+        info!(
+            "  Dropped {} duplicate advertisements",
+            duplicates.get(sensor_id).copied().unwrap_or(0)
+        );
+        info!(
+            "  Decode errors: {}",
+            format_counts(decode_errors.get(sensor_id))
+        );
+        info!(
+            "  Rejected outliers: {}",
+            format_counts(Some(&avg_data.rejected))
+        );
+    }
+
+    for (sensor_id, avg_data) in air_quality_averages.iter() {
+        info!("{} air quality summary for {}:", label, avg_data.name);
+        info!(
+            "  Average temperature: {}°C",
+            format_optional(avg_data.temperature, 2)
+        );
+        info!(
+            "  Average humidity: {}%",
+            format_optional(avg_data.humidity, 2)
+        );
+        info!(
+            "  Average pressure: {} hPa",
+            format_optional(avg_data.pressure, 2)
+        );
+        info!(
+            "  Average PM2.5: {} µg/m³",
+            format_optional(avg_data.pm2_5, 1)
+        );
+        info!("  Average CO2: {} ppm", format_optional(avg_data.co2, 1));
+        info!(
+            "  Average VOC index: {}",
+            format_optional(avg_data.voc_index, 1)
+        );
+        info!(
+            "  Average NOx index: {}",
+            format_optional(avg_data.nox_index, 1)
+        );
         info!(
-            "Collection interval complete at: {}",
-            format_datetime(&end_time)
+            "  Average luminosity: {} lx",
+            format_optional(avg_data.luminosity, 2)
         );
+        info!(
+            "  Based on {} samples covering {:.1}% of the window",
+            avg_data.samples,
+            avg_data.coverage * 100.0
+        );
+        info!(
+            "  Samples per adapter: {}",
+            format_counts(Some(&count_adapters(
+                air_measurements[sensor_id.as_str()]
+                    .iter()
+                    .map(|m| m.adapter.as_deref())
+            )))
+        );
+        info!(
+            "  Dropped {} duplicate advertisements",
+            duplicates.get(sensor_id).copied().unwrap_or(0)
+        );
+        info!(
+            "  Decode errors: {}",
+            format_counts(decode_errors.get(sensor_id))
+        );
+    }
 
-        // Data processing phase - calculate averages from all collected measurements
-        let sensor_averages = calculate_averages(&measurements, &config);
+    // Sensors that only repeated cached advertisements or sent undecodable
+    // data have no averages to report
+    let mut silent_sensors: Vec<&String> = duplicates
+        .keys()
+        .chain(decode_errors.keys())
+        .filter(|id| !sensor_averages.contains_key(*id))
+        .filter(|id| !air_quality_averages.contains_key(*id))
+        .collect();
+    silent_sensors.sort();
+    silent_sensors.dedup();
+    for sensor_id in silent_sensors {
+        warn!(
+            "No new data from {}, dropped {} duplicate advertisements, decode errors: {}",
+            sensor_id,
+            duplicates.get(sensor_id).copied().unwrap_or(0),
+            format_counts(decode_errors.get(sensor_id))
+        );
+    }
 
-        // Data storage phase - persist averaged data to database
-        for (sensor_id, avg_data) in sensor_averages.iter() {
-            // Store atmospheric data (temperature, humidity, pressure)
-            if let Err(e) = store_sensor_data(sensor_id, avg_data, &config.database_url).await {
-                error!(
-                    "Failed to store sensor data for sensor {}: {}",
-                    sensor_id, e
-                );
-            } else {
-                info!("Successfully stored sensor data for sensor {}", sensor_id);
-            }
+    // Warning if no data collected
+    if sensor_averages.is_empty() && air_quality_averages.is_empty() {
+        warn!("No data collected during this {} window!", label);
+    }
 
-            // Store movement data (acceleration, movement counter)
-            if let Err(e) = store_movement_data(sensor_id, avg_data, &config.database_url).await {
-                error!(
-                    "Failed to store movement data for sensor {}: {}",
-                    sensor_id, e
-                );
-            } else {
-                info!("Successfully stored movement data for sensor {}", sensor_id);
+    let mut aggregates = WindowAggregates {
+        sensor_averages,
+        air_quality_averages,
+        discovered: Vec::new(),
+    };
+
+    // Report unconfigured devices so they can be named and enabled
+    if !report_discovered {
+        return aggregates;
+    }
+    let mut discovered: Vec<DiscoveredTag> = discovered.into_values().collect();
+    discovered.sort_by(|a, b| a.mac.cmp(&b.mac));
+    for tag in discovered.iter() {
+        info!(
+            "Unconfigured Ruuvi device {}: data format {:#04x}, RSSI {} dBm via {}, {} advertisements, sample: {}",
+            tag.mac,
+            tag.data_format,
+            format_optional(tag.rssi, 0),
+            tag.adapter,
+            tag.advertisements,
+            tag.sample
+                .as_ref()
+                .map_or("not decodable".to_string(), describe_reading)
+        );
+    }
+
+    if config.discovery_mode == DiscoveryMode::Store {
+        aggregates.discovered = discovered;
+    }
+    aggregates
+}
+
+/// Start the configured reading source and run the main loop on it
+async fn run(config: SensorConfig) -> Result<(), Box<dyn std::error::Error>> {
+    match config.reading_source {
+        SourceKind::Bluetooth => {
+            let source = BluetoothSource::new(&config.bluetooth_adapters);
+            if config.history_sync {
+                // Backfill the outage while the scanners collect the current interval
+                let config = config.clone();
+                tokio::spawn(async move {
+                    let peer = BluerGattPeer::new(config.bluetooth_adapters.first().cloned());
+                    let store = PostgresSink::new(&config.database_url);
+                    sync_history(&peer, &store, &config).await;
+                });
             }
+            run_with_capture(config, source, SystemClock).await
         }
-
-        // Log summary of processed data for monitoring
-        for (_, avg_data) in sensor_averages.iter() {
-            info!("Summary for {}:", avg_data.name);
-            info!("  Average temperature: {:.2}°C", avg_data.temperature);
-            info!("  Average humidity: {:.2}%", avg_data.humidity);
-            info!("  Average pressure: {:.2} hPa", avg_data.pressure);
-            info!("  Average acceleration X: {:.3} g", avg_data.acceleration_x);
-            info!("  Average acceleration Y: {:.3} g", avg_data.acceleration_y);
-            info!("  Average acceleration Z: {:.3} g", avg_data.acceleration_z);
-            info!("  Movement counter delta: {}", avg_data.movement_counter);
-            info!("  Based on {} samples", avg_data.samples);
+        SourceKind::Simulated => {
+            let source = SimulatedSource::new(config.tags.keys());
+            run_with_capture(config, source, SystemClock).await
         }
-
-        // Warning if no data collected
-        if sensor_averages.is_empty() {
-            warn!("No data collected during this interval!");
+        SourceKind::Replay => {
+            let path = config.replay_file.clone().unwrap_or_default();
+            let (source, clock) = ReplaySource::open(&path, config.replay_speed).await?;
+            run_with_capture(config, source, clock).await
+        }
+        SourceKind::Gateway => {
+            let source =
+                GatewaySource::bind(&config.gateway_listen, config.gateway_token.clone()).await?;
+            run_with_capture(config, source, SystemClock).await
         }
+        SourceKind::Mqtt => {
+            let url = config.mqtt_url.clone().unwrap_or_default();
+            let source = MqttSource::connect(&url, &config.mqtt_topic)?;
+            run_with_capture(config, source, SystemClock).await
+        }
+    }
+}
 
-        // Wait until next interval should start
-        let total_elapsed = duration_to_seconds(OffsetDateTime::now_utc() - start_time);
-        if total_elapsed < COLLECTION_INTERVAL_SECS {
-            let wait_time = COLLECTION_INTERVAL_SECS - total_elapsed;
-            info!(
-                "Waiting {} seconds until next collection interval",
-                wait_time
-            );
-            sleep(Duration::from_secs(wait_time)).await;
+/// Run the main loop, capturing raw advertisements first if configured
+async fn run_with_capture<S: ReadingSource + Send, C: Clock>(
+    config: SensorConfig,
+    source: S,
+    clock: C,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match &config.capture_file {
+        Some(path) => {
+            let writer =
+                CaptureWriter::open(path, config.capture_max_bytes, config.capture_max_files)?;
+            let sink = PostgresSink::new(&config.database_url);
+            main_loop(config, CapturingSource::new(source, writer), clock, sink).await
+        }
+        None => {
+            let sink = PostgresSink::new(&config.database_url);
+            main_loop(config, source, clock, sink).await
         }
     }
 }
@@ -216,7 +568,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Run main loop or wait for shutdown signal
     tokio::select! {
-        result = main_loop(config) => {
+        result = run(config) => {
             match result {
                 Ok(_) => info!("Program completed successfully"),
                 Err(e) => error!("Fatal error: {}", e),
@@ -229,3 +581,218 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bluetooth::encoder::encode_ruuvi_data;
+    use models::{AverageAirQualityData, RuuviData};
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use time::{Duration, OffsetDateTime, UtcOffset};
+    use window::WindowTimeZone;
+
+    const TAG_MAC: &str = "CB:B8:33:4C:88:4F";
+
+    /// Sink keeping the stored atmospheric rows in memory
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        sensor_rows: Arc<Mutex<Vec<AverageData>>>,
+    }
+
+    impl AggregateSink for RecordingSink {
+        async fn store_sensor_data(&self, _: &str, avg_data: &AverageData) -> Result<(), String> {
+            self.sensor_rows.lock().unwrap().push(avg_data.clone());
+            Ok(())
+        }
+
+        async fn store_movement_data(&self, _: &str, _: &AverageData) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn store_battery_data(&self, _: &str, _: &AverageData) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn store_signal_data(&self, _: &str, _: &AverageData) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn store_air_quality_data(
+            &self,
+            _: &str,
+            _: &AverageAirQualityData,
+        ) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn store_discovered_tag(&self, _: &DiscoveredTag) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    fn test_config(aggregation_windows: Vec<u64>) -> SensorConfig {
+        SensorConfig {
+            tags: HashMap::from([(TAG_MAC.to_string(), "Test".to_string())]),
+            database_url: String::new(),
+            encryption_keys: HashMap::new(),
+            bluetooth_adapters: Vec::new(),
+            discovery_mode: DiscoveryMode::Off,
+            reading_source: SourceKind::Replay,
+            capture_file: None,
+            capture_max_bytes: 0,
+            capture_max_files: 0,
+            replay_file: None,
+            replay_speed: 0.0,
+            gateway_listen: String::new(),
+            gateway_token: None,
+            mqtt_url: None,
+            mqtt_topic: String::new(),
+            history_sync: false,
+            history_max_days: 0,
+            aggregation_windows,
+            window_time_zone: WindowTimeZone::Fixed(UtcOffset::UTC),
+            outlier_filter: true,
+            hampel_threshold: 3.0,
+        }
+    }
+
+    /// Write a capture file with one advertisement per reading, in the given order
+    fn write_capture(path: &std::path::Path, readings: &[(OffsetDateTime, f32)]) {
+        let mut file = std::fs::File::create(path).unwrap();
+        for (index, &(received_at, temperature)) in readings.iter().enumerate() {
+            let data = RuuviData {
+                temperature: Some(temperature),
+                humidity: Some(45.0),
+                pressure: Some(1005.0),
+                acceleration_x: Some(0.0),
+                acceleration_y: Some(0.0),
+                acceleration_z: Some(1.0),
+                movement_counter: Some(0),
+                battery_voltage: Some(3000),
+                tx_power: Some(4),
+                measurement_sequence: Some(index as u16),
+                rssi: None,
+                adapter: None,
+                received_at: None,
+            };
+            let advertisement = Advertisement {
+                received_at,
+                adapter: "replay".to_string(),
+                address: TAG_MAC.to_string(),
+                random_address: false,
+                rssi: Some(-60),
+                payload: encode_ruuvi_data(&data, Some([0xCB, 0xB8, 0x33, 0x4C, 0x88, 0x4F]))
+                    .to_vec(),
+            };
+            writeln!(file, "{}", serde_json::to_string(&advertisement).unwrap()).unwrap();
+        }
+    }
+
+    /// Replay readings through the main loop and summarise the stored rows as
+    /// (resolution, start and end relative to `hour`, partial, samples)
+    async fn replay(
+        name: &str,
+        readings: &[(OffsetDateTime, f32)],
+        aggregation_windows: Vec<u64>,
+        hour: OffsetDateTime,
+    ) -> (Vec<(u64, Duration, Duration, bool, i32)>, Vec<AverageData>) {
+        let path = std::env::temp_dir().join(format!("{}-{}.jsonl", name, std::process::id()));
+        write_capture(&path, readings);
+        let (source, clock) = ReplaySource::open(path.to_str().unwrap(), 0.0)
+            .await
+            .unwrap();
+        let sink = RecordingSink::default();
+
+        main_loop(
+            test_config(aggregation_windows),
+            source,
+            clock,
+            sink.clone(),
+        )
+        .await
+        .unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let mut rows = sink.sensor_rows.lock().unwrap().clone();
+        rows.sort_by_key(|row| (row.resolution, row.window_start));
+        let summary = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.resolution as u64,
+                    row.window_start - hour,
+                    row.time - hour,
+                    row.partial,
+                    row.samples,
+                )
+            })
+            .collect();
+        (summary, rows)
+    }
+
+    #[tokio::test]
+    async fn main_loop_stores_every_window() {
+        // One advertisement every 10 seconds between 12:10 and 13:05, reporting
+        // 20°C before 12:30 and 22°C after
+        let start = OffsetDateTime::from_unix_timestamp(1_704_111_000).unwrap(); // 2024-01-01 12:10 UTC
+        let readings: Vec<(OffsetDateTime, f32)> = (0..330)
+            .map(|index| {
+                let temperature = if index < 120 { 20.0 } else { 22.0 };
+                (start + Duration::seconds(10 * index), temperature)
+            })
+            .collect();
+        let hour = start - Duration::minutes(10);
+        let last = start + Duration::seconds(3290);
+
+        let (summary, rows) = replay("main-loop", &readings, vec![1800, 3600], hour).await;
+
+        assert_eq!(
+            summary,
+            vec![
+                (1800, Duration::ZERO, Duration::minutes(30), true, 120),
+                (
+                    1800,
+                    Duration::minutes(30),
+                    Duration::minutes(60),
+                    false,
+                    180
+                ),
+                (1800, Duration::minutes(60), last - hour, true, 30),
+                (3600, Duration::ZERO, Duration::minutes(60), true, 300),
+                (3600, Duration::minutes(60), last - hour, true, 30),
+            ]
+        );
+        assert_eq!(rows[0].temperature, Some(20.0));
+        assert_eq!(rows[1].temperature, Some(22.0));
+    }
+
+    #[tokio::test]
+    async fn main_loop_routes_readings_by_receive_time() {
+        let hour = OffsetDateTime::from_unix_timestamp(1_704_110_400).unwrap(); // 2024-01-01 12:00 UTC
+        let at = |seconds: i64| hour + Duration::seconds(seconds);
+        // A gateway relays the 12:29:55 reading after the first one past 12:30
+        let readings = [
+            (at(1790), 20.0),
+            (at(1800), 22.0),
+            (at(1795), 30.0),
+            (at(1810), 22.0),
+        ];
+
+        let (summary, rows) = replay("main-loop-late", &readings, vec![1800, 3600], hour).await;
+
+        // The late reading missed the stored half hour but is part of the hour
+        let end = Duration::seconds(1810);
+        assert_eq!(
+            summary,
+            vec![
+                (1800, Duration::ZERO, Duration::minutes(30), true, 1),
+                (1800, Duration::minutes(30), end, true, 2),
+                (3600, Duration::ZERO, end, true, 4),
+            ]
+        );
+        assert_eq!(rows[0].temperature, Some(20.0));
+        assert_eq!(rows[1].temperature, Some(22.0));
+    }
+}
@@ -1,35 +1,299 @@
 /// Data structures for sensor readings and processed data
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use time::OffsetDateTime;
 
 /// Raw sensor data decoded from RuuviTag Bluetooth advertisements
 ///
-/// This represents a single reading from a RuuviTag sensor using data format 5.
-/// All values are decoded from the 24-byte manufacturer data payload.
+/// This represents a single reading from a RuuviTag sensor using data format 3, 5 or 8.
+/// Values not carried by the tag's data format, or reported as "not available"
+/// by the tag (e.g. a missing or faulty sensor), are left as None.
 #[derive(Debug, Clone)]
 pub struct RuuviData {
-    pub temperature: f32,
-    pub humidity: f32,
-    pub pressure: f32,
-    pub acceleration_x: f32,
-    pub acceleration_y: f32,
-    pub acceleration_z: f32,
-    pub movement_counter: u8,
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+    pub pressure: Option<f32>,
+    /// Acceleration X in g (not available in data format 8)
+    pub acceleration_x: Option<f32>,
+    /// Acceleration Y in g (not available in data format 8)
+    pub acceleration_y: Option<f32>,
+    /// Acceleration Z in g (not available in data format 8)
+    pub acceleration_z: Option<f32>,
+    /// Movement counter (not available in data format 3)
+    pub movement_counter: Option<u8>,
+    /// Battery voltage in millivolts
+    pub battery_voltage: Option<u16>,
+    /// Transmit power in dBm (not available in data format 3)
+    pub tx_power: Option<i8>,
+    /// Measurement sequence number (not available in data format 3)
+    pub measurement_sequence: Option<u16>,
+    /// Received signal strength of the advertisement in dBm (not part of the payload)
+    pub rssi: Option<i16>,
+    /// Bluetooth adapter that received the advertisement (not part of the payload)
+    pub adapter: Option<String>,
+    /// Time the advertisement was received (not part of the payload)
+    pub received_at: Option<OffsetDateTime>,
 }
 
 /// Processed sensor data representing averages over a collection interval
 ///
 /// This structure contains averaged values from multiple RuuviData readings
-/// along with metadata about the collection period.
+/// along with metadata about the collection period. Each metric is averaged
+/// over the readings that carried a valid value for it, weighted by time, and
+/// is None if no reading in the interval did.
 #[derive(Debug, Clone)]
 pub struct AverageData {
-    pub temperature: f32,
-    pub humidity: f32,
-    pub pressure: f32,
-    pub acceleration_x: f32,
-    pub acceleration_y: f32,
-    pub acceleration_z: f32,
-    pub movement_counter: u32,
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+    pub pressure: Option<f32>,
+    pub acceleration_x: Option<f32>,
+    pub acceleration_y: Option<f32>,
+    pub acceleration_z: Option<f32>,
+    /// Spread of each metric over the interval, None if no reading carried the metric
+    pub temperature_stats: Option<MetricStats>,
+    pub humidity_stats: Option<MetricStats>,
+    pub pressure_stats: Option<MetricStats>,
+    pub acceleration_x_stats: Option<MetricStats>,
+    pub acceleration_y_stats: Option<MetricStats>,
+    pub acceleration_z_stats: Option<MetricStats>,
+    /// Movement counter delta, None if the sensor does not report movement
+    pub movement_counter: Option<u32>,
+    /// Lowest battery voltage seen during the interval (mV)
+    pub battery_voltage: Option<u16>,
+    /// Most recent transmit power reported during the interval (dBm)
+    pub tx_power: Option<i8>,
+    /// Weakest, average and strongest received signal strength during the interval (dBm)
+    pub rssi_min: Option<i16>,
+    pub rssi_avg: Option<f32>,
+    pub rssi_max: Option<i16>,
+    /// End of the aggregation window
     pub time: OffsetDateTime,
     pub name: String,
     pub samples: i32,
+    /// Readings whose temperature, humidity or pressure was rejected as an outlier
+    pub rejected_atmospheric: i32,
+    /// Readings whose acceleration was rejected as an outlier
+    pub rejected_movement: i32,
+    /// Number of values rejected as outliers per metric
+    pub rejected: HashMap<&'static str, u32>,
+    /// Length of the aggregation window in seconds
+    pub resolution: i32,
+    /// Start of the aggregation window
+    pub window_start: OffsetDateTime,
+    /// Whether collection did not cover the whole window (service started or stopped within it)
+    pub partial: bool,
+    /// Fraction of the window covered by readings (0.0 - 1.0)
+    pub coverage: f32,
+}
+
+impl AverageData {
+    /// Number of values of the given metric rejected as outliers
+    pub fn rejected_values(&self, metric: &str) -> i32 {
+        self.rejected.get(metric).map_or(0, |&count| count as i32)
+    }
+}
+
+/// Bounds of an aggregation window, stamped on every row aggregated over it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowBounds {
+    pub start: OffsetDateTime,
+    pub end: OffsetDateTime,
+    /// Length of the window in seconds
+    pub resolution: u64,
+    /// Whether collection did not cover the whole window
+    pub partial: bool,
+}
+
+/// Distribution of a single metric over a collection interval
+///
+/// Complements the mean so that short excursions (e.g. a freezer door left
+/// open for ten minutes) remain visible in the stored data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricStats {
+    pub min: f32,
+    pub max: f32,
+    /// Population standard deviation of the readings in the interval
+    pub std_dev: f32,
+    pub median: f32,
+}
+
+impl MetricStats {
+    /// Round all statistics to the given number of decimal places
+    pub fn rounded(&self, decimals: i32) -> Self {
+        let factor = 10f32.powi(decimals);
+        let round = |value: f32| (value * factor).round() / factor;
+        MetricStats {
+            min: round(self.min),
+            max: round(self.max),
+            std_dev: round(self.std_dev),
+            median: round(self.median),
+        }
+    }
+}
+
+/// A single Ruuvi manufacturer data advertisement as received over the air
+///
+/// This is the undecoded form in which readings travel from the scanner to
+/// the aggregation loop, and the form in which it is written to and replayed
+/// from capture files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Advertisement {
+    /// Time the advertisement was received
+    #[serde(with = "time::serde::rfc3339")]
+    pub received_at: OffsetDateTime,
+    /// Name of the Bluetooth adapter that received the advertisement (e.g. hci0)
+    #[serde(default)]
+    pub adapter: String,
+    /// BLE address of the sending device (uppercase)
+    pub address: String,
+    /// Whether the BLE address is a random (private) address
+    #[serde(default)]
+    pub random_address: bool,
+    /// Received signal strength in dBm
+    pub rssi: Option<i16>,
+    /// Manufacturer data following the Ruuvi manufacturer ID
+    #[serde(
+        serialize_with = "crate::utils::serialize_hex",
+        deserialize_with = "crate::utils::deserialize_hex"
+    )]
+    pub payload: Vec<u8>,
+}
+
+/// A single entry of the history log stored on a RuuviTag
+///
+/// The tag logs temperature, humidity and pressure at a fixed interval
+/// (5 minutes with default firmware settings). Values missing from the
+/// downloaded log are left as None.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryRecord {
+    pub time: OffsetDateTime,
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+    pub pressure: Option<f32>,
+}
+
+/// A Ruuvi device heard during a collection interval that is not configured
+///
+/// Reported in discovery mode so that new tags can be named and enabled.
+#[derive(Debug, Clone)]
+pub struct DiscoveredTag {
+    /// MAC address of the device (uppercase)
+    pub mac: String,
+    /// Data format of the latest advertisement
+    pub data_format: u8,
+    /// Received signal strength of the latest advertisement in dBm
+    pub rssi: Option<i16>,
+    /// Bluetooth adapter that received the latest advertisement
+    pub adapter: String,
+    /// Decoded sample of the latest advertisement, None if it could not be decoded
+    /// (e.g. encrypted data format 8 without a configured key)
+    pub sample: Option<SensorReading>,
+    /// Number of advertisements received during the interval
+    pub advertisements: i32,
+    /// Time of the latest advertisement
+    pub last_seen: OffsetDateTime,
+}
+
+/// Raw air quality data decoded from Ruuvi Air Bluetooth advertisements
+///
+/// This represents a single reading from a Ruuvi Air device using data format 6 or E1.
+/// Particulate matter sizes only present in the extended format E1 are left as None
+/// for format 6 readings, as are values the device reported as "not available".
+#[derive(Debug, Clone)]
+pub struct AirQualityData {
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+    pub pressure: Option<f32>,
+    /// Particulate matter PM1.0 in µg/m³ (format E1 only)
+    pub pm1_0: Option<f32>,
+    /// Particulate matter PM2.5 in µg/m³
+    pub pm2_5: Option<f32>,
+    /// Particulate matter PM4.0 in µg/m³ (format E1 only)
+    pub pm4_0: Option<f32>,
+    /// Particulate matter PM10 in µg/m³ (format E1 only)
+    pub pm10_0: Option<f32>,
+    /// Carbon dioxide concentration in ppm
+    pub co2: Option<u16>,
+    /// Volatile organic compound index (1-500)
+    pub voc_index: Option<u16>,
+    /// Nitrogen oxides index (1-500)
+    pub nox_index: Option<u16>,
+    /// Luminosity in lux
+    pub luminosity: Option<f32>,
+    /// Measurement sequence number (8 bits in format 6, 24 bits in format E1)
+    pub measurement_sequence: Option<u32>,
+    /// Bluetooth adapter that received the advertisement (not part of the payload)
+    pub adapter: Option<String>,
+    /// Time the advertisement was received (not part of the payload)
+    pub received_at: Option<OffsetDateTime>,
+}
+
+/// A single decoded advertisement from any supported Ruuvi device
+#[derive(Debug, Clone)]
+pub enum SensorReading {
+    /// Environmental and movement reading from a RuuviTag
+    Tag(RuuviData),
+    /// Air quality reading from a Ruuvi Air
+    AirQuality(AirQualityData),
+}
+
+impl SensorReading {
+    /// Record the Bluetooth adapter that received the reading
+    pub fn set_adapter(&mut self, adapter: &str) {
+        let field = match self {
+            SensorReading::Tag(data) => &mut data.adapter,
+            SensorReading::AirQuality(data) => &mut data.adapter,
+        };
+        *field = Some(adapter.to_string());
+    }
+
+    /// Record the time the reading was received
+    pub fn set_received_at(&mut self, received_at: OffsetDateTime) {
+        let field = match self {
+            SensorReading::Tag(data) => &mut data.received_at,
+            SensorReading::AirQuality(data) => &mut data.received_at,
+        };
+        *field = Some(received_at);
+    }
+
+    /// Measurement sequence number of the reading, if the data format carries one
+    pub fn measurement_sequence(&self) -> Option<u32> {
+        match self {
+            SensorReading::Tag(data) => data.measurement_sequence.map(u32::from),
+            SensorReading::AirQuality(data) => data.measurement_sequence,
+        }
+    }
+}
+
+/// Processed air quality data representing averages over a collection interval
+///
+/// This structure contains averaged values from multiple AirQualityData readings
+/// along with metadata about the collection period. Each metric is averaged
+/// over the readings that carried a valid value for it, and is None if no
+/// reading in the interval did.
+#[derive(Debug, Clone)]
+pub struct AverageAirQualityData {
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+    pub pressure: Option<f32>,
+    pub pm1_0: Option<f32>,
+    pub pm2_5: Option<f32>,
+    pub pm4_0: Option<f32>,
+    pub pm10_0: Option<f32>,
+    pub co2: Option<f32>,
+    pub voc_index: Option<f32>,
+    pub nox_index: Option<f32>,
+    pub luminosity: Option<f32>,
+    /// End of the aggregation window
+    pub time: OffsetDateTime,
+    pub name: String,
+    pub samples: i32,
+    /// Length of the aggregation window in seconds
+    pub resolution: i32,
+    /// Start of the aggregation window
+    pub window_start: OffsetDateTime,
+    /// Whether collection did not cover the whole window (service started or stopped within it)
+    pub partial: bool,
+    /// Fraction of the window covered by readings (0.0 - 1.0)
+    pub coverage: f32,
 }
@@ -0,0 +1,253 @@
+/// Pluggable destinations for the aggregates of finished windows
+use log::{error, info};
+use std::collections::HashMap;
+use std::future::Future;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+
+use crate::database::operations::{
+    fetch_last_sensor_time, store_air_quality_data, store_battery_data, store_discovered_tag,
+    store_history_data, store_movement_data, store_sensor_data, store_signal_data,
+};
+use crate::models::{AverageAirQualityData, AverageData, DiscoveredTag, HistoryRecord};
+
+/// A destination for the aggregates produced at the end of each window
+///
+/// The main loop only depends on this trait, so the PostgreSQL database can be
+/// replaced, e.g. by an in-memory sink when testing the pipeline.
+pub trait AggregateSink {
+    /// Store atmospheric data (temperature, humidity, pressure) of a sensor
+    fn store_sensor_data(
+        &self,
+        sensor_id: &str,
+        avg_data: &AverageData,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+
+    /// Store movement data (acceleration, movement counter) of a sensor
+    fn store_movement_data(
+        &self,
+        sensor_id: &str,
+        avg_data: &AverageData,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+
+    /// Store battery data (battery voltage, TX power) of a sensor
+    fn store_battery_data(
+        &self,
+        sensor_id: &str,
+        avg_data: &AverageData,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+
+    /// Store signal data (minimum, average and maximum RSSI) of a sensor
+    fn store_signal_data(
+        &self,
+        sensor_id: &str,
+        avg_data: &AverageData,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+
+    /// Store air quality data of a Ruuvi Air
+    fn store_air_quality_data(
+        &self,
+        sensor_id: &str,
+        avg_data: &AverageAirQualityData,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+
+    /// Record an unconfigured Ruuvi device heard in discovery mode
+    fn store_discovered_tag(
+        &self,
+        tag: &DiscoveredTag,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+/// A destination for the entries downloaded from the on-tag history logs
+///
+/// The history sync only depends on this trait, so it can be tested without
+/// a database.
+pub trait HistoryStore {
+    /// Time of the latest stored atmospheric data of a sensor, if any
+    fn last_sensor_time(
+        &self,
+        sensor_id: &str,
+    ) -> impl Future<Output = Result<Option<OffsetDateTime>, String>> + Send;
+
+    /// Store history log entries of a sensor as atmospheric data
+    fn store_history(
+        &self,
+        sensor_id: &str,
+        name: &str,
+        records: &[HistoryRecord],
+    ) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+/// Sink storing aggregates in the PostgreSQL database
+#[derive(Debug, Clone)]
+pub struct PostgresSink {
+    database_url: String,
+}
+
+impl PostgresSink {
+    /// Create a sink for the given database
+    ///
+    /// # Arguments
+    /// * `database_url` - PostgreSQL connection string
+    pub fn new(database_url: &str) -> Self {
+        PostgresSink {
+            database_url: database_url.to_string(),
+        }
+    }
+}
+
+impl AggregateSink for PostgresSink {
+    async fn store_sensor_data(
+        &self,
+        sensor_id: &str,
+        avg_data: &AverageData,
+    ) -> Result<(), String> {
+        store_sensor_data(sensor_id, avg_data, &self.database_url).await
+    }
+
+    async fn store_movement_data(
+        &self,
+        sensor_id: &str,
+        avg_data: &AverageData,
+    ) -> Result<(), String> {
+        store_movement_data(sensor_id, avg_data, &self.database_url).await
+    }
+
+    async fn store_battery_data(
+        &self,
+        sensor_id: &str,
+        avg_data: &AverageData,
+    ) -> Result<(), String> {
+        store_battery_data(sensor_id, avg_data, &self.database_url).await
+    }
+
+    async fn store_signal_data(
+        &self,
+        sensor_id: &str,
+        avg_data: &AverageData,
+    ) -> Result<(), String> {
+        store_signal_data(sensor_id, avg_data, &self.database_url).await
+    }
+
+    async fn store_air_quality_data(
+        &self,
+        sensor_id: &str,
+        avg_data: &AverageAirQualityData,
+    ) -> Result<(), String> {
+        store_air_quality_data(sensor_id, avg_data, &self.database_url).await
+    }
+
+    async fn store_discovered_tag(&self, tag: &DiscoveredTag) -> Result<(), String> {
+        store_discovered_tag(tag, &self.database_url).await
+    }
+}
+
+impl HistoryStore for PostgresSink {
+    async fn last_sensor_time(&self, sensor_id: &str) -> Result<Option<OffsetDateTime>, String> {
+        fetch_last_sensor_time(sensor_id, &self.database_url).await
+    }
+
+    async fn store_history(
+        &self,
+        sensor_id: &str,
+        name: &str,
+        records: &[HistoryRecord],
+    ) -> Result<(), String> {
+        store_history_data(sensor_id, name, records, &self.database_url).await
+    }
+}
+
+/// Aggregates of a finished window, queued for storage
+#[derive(Debug, Default)]
+pub struct WindowAggregates {
+    /// RuuviTag averages per sensor MAC address
+    pub sensor_averages: HashMap<String, AverageData>,
+    /// Ruuvi Air averages per sensor MAC address
+    pub air_quality_averages: HashMap<String, AverageAirQualityData>,
+    /// Unconfigured devices to record (discovery store mode)
+    pub discovered: Vec<DiscoveredTag>,
+}
+
+/// Store queued window aggregates until the queue is closed
+///
+/// Runs as its own task so that slow database writes, each retried for
+/// minutes on connection failures, never stall the collection of new
+/// advertisements. Aggregates of later windows wait in the queue meanwhile.
+///
+/// # Arguments
+/// * `sink` - Destination of the aggregates
+/// * `queue` - Aggregates of finished windows, in the order they finished
+pub async fn run_storage<K: AggregateSink>(
+    sink: K,
+    mut queue: mpsc::UnboundedReceiver<WindowAggregates>,
+) {
+    while let Some(aggregates) = queue.recv().await {
+        store_aggregates(&sink, &aggregates).await;
+    }
+}
+
+/// Store the aggregates of a single window, logging the outcome of each write
+async fn store_aggregates<K: AggregateSink>(sink: &K, aggregates: &WindowAggregates) {
+    for (sensor_id, avg_data) in aggregates.sensor_averages.iter() {
+        // Store atmospheric data (temperature, humidity, pressure)
+        if let Err(e) = sink.store_sensor_data(sensor_id, avg_data).await {
+            error!(
+                "Failed to store sensor data for sensor {}: {}",
+                sensor_id, e
+            );
+        } else {
+            info!("Successfully stored sensor data for sensor {}", sensor_id);
+        }
+
+        // Store movement data (acceleration, movement counter)
+        if let Err(e) = sink.store_movement_data(sensor_id, avg_data).await {
+            error!(
+                "Failed to store movement data for sensor {}: {}",
+                sensor_id, e
+            );
+        } else {
+            info!("Successfully stored movement data for sensor {}", sensor_id);
+        }
+
+        // Store battery data (battery voltage, TX power)
+        if let Err(e) = sink.store_battery_data(sensor_id, avg_data).await {
+            error!(
+                "Failed to store battery data for sensor {}: {}",
+                sensor_id, e
+            );
+        } else {
+            info!("Successfully stored battery data for sensor {}", sensor_id);
+        }
+
+        // Store signal data (minimum, average and maximum RSSI)
+        if let Err(e) = sink.store_signal_data(sensor_id, avg_data).await {
+            error!(
+                "Failed to store signal data for sensor {}: {}",
+                sensor_id, e
+            );
+        } else {
+            info!("Successfully stored signal data for sensor {}", sensor_id);
+        }
+    }
+
+    // Store air quality data (particulate matter, CO2, VOC, NOx, luminosity)
+    for (sensor_id, avg_data) in aggregates.air_quality_averages.iter() {
+        if let Err(e) = sink.store_air_quality_data(sensor_id, avg_data).await {
+            error!(
+                "Failed to store air quality data for sensor {}: {}",
+                sensor_id, e
+            );
+        } else {
+            info!(
+                "Successfully stored air quality data for sensor {}",
+                sensor_id
+            );
+        }
+    }
+
+    for tag in aggregates.discovered.iter() {
+        if let Err(e) = sink.store_discovered_tag(tag).await {
+            error!("Failed to store discovered tag {}: {}", tag.mac, e);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+/// Advertisements received by the BlueZ scanners
+use log::info;
+use tokio::sync::mpsc;
+
+use super::ReadingSource;
+use crate::bluetooth::health::{AdapterHealthMap, AdapterStatus};
+use crate::bluetooth::spawn_scanners;
+use crate::models::Advertisement;
+
+const ADVERTISEMENT_CHANNEL_CAPACITY: usize = 1024; // Advertisements buffered between scanners and main loop
+
+/// Reading source backed by one BlueZ scanner per configured adapter
+pub struct BluetoothSource {
+    advertisements: mpsc::Receiver<Advertisement>,
+    health: AdapterHealthMap,
+}
+
+impl BluetoothSource {
+    /// Start the scanners for the given adapters
+    ///
+    /// # Arguments
+    /// * `adapters` - Names of the Bluetooth adapters to scan on, or empty for the default adapter
+    pub fn new(adapters: &[String]) -> Self {
+        let (tx, advertisements) = mpsc::channel(ADVERTISEMENT_CHANNEL_CAPACITY);
+        let health = AdapterHealthMap::default();
+        spawn_scanners(adapters, tx, &health);
+
+        BluetoothSource {
+            advertisements,
+            health,
+        }
+    }
+}
+
+impl ReadingSource for BluetoothSource {
+    async fn next_advertisement(&mut self) -> Option<Advertisement> {
+        self.advertisements.recv().await
+    }
+
+    fn log_health(&self) {
+        let mut adapters: Vec<(String, AdapterStatus)> = self
+            .health
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(name, status)| (name.clone(), status.clone()))
+            .collect();
+        adapters.sort_by(|a, b| a.0.cmp(&b.0));
+        for (adapter, status) in adapters {
+            info!(
+                "Bluetooth adapter {}: {}, {} failures, {} power cycles",
+                adapter, status.health, status.total_failures, status.power_cycles
+            );
+        }
+    }
+}
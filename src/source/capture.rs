@@ -0,0 +1,227 @@
+/// Recording of raw advertisements to rotating JSON Lines files
+use log::{error, info, warn};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+
+use super::ReadingSource;
+use crate::models::Advertisement;
+
+const CAPTURE_QUEUE_SIZE: usize = 4096; // Advertisements waiting to be written to the capture file
+
+/// Appends advertisements to a JSON Lines file, rotating it when it grows too large
+///
+/// Rotated files are renamed with a numeric suffix (capture.jsonl.1 being the
+/// most recent), and the oldest file is deleted once `max_files` is exceeded.
+pub struct CaptureWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: u32,
+    file: BufWriter<File>,
+    written: u64,
+}
+
+impl CaptureWriter {
+    /// Open the capture file for appending
+    ///
+    /// # Arguments
+    /// * `path` - Path of the capture file
+    /// * `max_bytes` - Size at which the file is rotated
+    /// * `max_files` - Number of rotated files to keep
+    ///
+    /// # Returns
+    /// The writer, or error if the file cannot be opened
+    pub fn open(path: &str, max_bytes: u64, max_files: u32) -> std::io::Result<Self> {
+        let path = PathBuf::from(path);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+
+        Ok(CaptureWriter {
+            path,
+            max_bytes,
+            max_files,
+            file: BufWriter::new(file),
+            written,
+        })
+    }
+
+    /// Append a single advertisement as one JSON line
+    pub fn write(&mut self, advertisement: &Advertisement) -> std::io::Result<()> {
+        if self.written >= self.max_bytes {
+            self.rotate()?;
+        }
+
+        let mut line = serde_json::to_vec(advertisement)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shift the rotated files by one and start a new capture file
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.written = 0;
+        info!("Rotated capture file {}", self.path.display());
+        Ok(())
+    }
+
+    /// Write queued advertisements until the queue is closed
+    ///
+    /// Blocks on file I/O, so it runs on a blocking thread. The file is flushed
+    /// whenever the queue has been drained.
+    fn run(mut self, mut queue: mpsc::Receiver<Advertisement>) {
+        while let Some(advertisement) = queue.blocking_recv() {
+            if let Err(e) = self.write(&advertisement) {
+                error!("Failed to write capture file: {}", e);
+            }
+            if queue.is_empty() {
+                if let Err(e) = self.file.flush() {
+                    error!("Failed to flush capture file: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Path of the rotated file with the given index
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+}
+
+/// Reading source wrapper that records every advertisement of the inner source
+///
+/// Advertisements are recorded before any decoding or filtering, so captures
+/// include unconfigured tags and undecodable payloads. The file is written by
+/// a blocking writer task, so that a slow disk never stalls the runtime; if
+/// the writer falls behind by `CAPTURE_QUEUE_SIZE` advertisements, further
+/// ones are left out of the capture.
+pub struct CapturingSource<S> {
+    inner: S,
+    queue: mpsc::Sender<Advertisement>,
+    /// Advertisements left out of the capture because the writer fell behind
+    dropped: u64,
+}
+
+impl<S: ReadingSource> CapturingSource<S> {
+    /// Wrap a source so that its advertisements are written to the capture file
+    ///
+    /// Starts the writer task, so it must be called within the Tokio runtime.
+    pub fn new(inner: S, writer: CaptureWriter) -> Self {
+        let (queue, queued) = mpsc::channel(CAPTURE_QUEUE_SIZE);
+        tokio::task::spawn_blocking(move || writer.run(queued));
+        CapturingSource {
+            inner,
+            queue,
+            dropped: 0,
+        }
+    }
+}
+
+impl<S: ReadingSource + Send> ReadingSource for CapturingSource<S> {
+    async fn next_advertisement(&mut self) -> Option<Advertisement> {
+        let advertisement = self.inner.next_advertisement().await?;
+        if self.queue.try_send(advertisement.clone()).is_err() {
+            self.dropped += 1;
+        }
+        Some(advertisement)
+    }
+
+    fn log_health(&self) {
+        self.inner.log_health();
+        if self.dropped > 0 {
+            warn!(
+                "Capture file writer fell behind, {} advertisements not captured so far",
+                self.dropped
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use time::OffsetDateTime;
+
+    /// Source yielding a fixed list of advertisements
+    struct ListSource(VecDeque<Advertisement>);
+
+    impl ReadingSource for ListSource {
+        async fn next_advertisement(&mut self) -> Option<Advertisement> {
+            self.0.pop_front()
+        }
+    }
+
+    fn advertisement(index: i64) -> Advertisement {
+        Advertisement {
+            received_at: OffsetDateTime::from_unix_timestamp(1_704_110_400 + index).unwrap(),
+            adapter: "hci0".to_string(),
+            address: "CB:B8:33:4C:88:4F".to_string(),
+            random_address: false,
+            rssi: Some(-60),
+            payload: vec![0x05, index as u8],
+        }
+    }
+
+    #[tokio::test]
+    async fn captures_advertisements_and_rotates() {
+        let path = std::env::temp_dir().join(format!("capture-{}.jsonl", std::process::id()));
+        let line_length = serde_json::to_vec(&advertisement(0)).unwrap().len() as u64 + 1;
+        // Rotate after every two advertisements, keeping one old file
+        let writer = CaptureWriter::open(path.to_str().unwrap(), 2 * line_length, 1).unwrap();
+        let inner = ListSource((0..5).map(advertisement).collect());
+
+        let mut source = CapturingSource::new(inner, writer);
+        let mut yielded = 0;
+        while source.next_advertisement().await.is_some() {
+            yielded += 1;
+        }
+        assert_eq!(yielded, 5);
+
+        // Closing the queue lets the writer finish
+        drop(source);
+        let read_lines = |path: &std::path::Path| -> Vec<Advertisement> {
+            std::fs::read_to_string(path)
+                .unwrap_or_default()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+        let rotated = path.with_extension("jsonl.1");
+        let mut captured = Vec::new();
+        for _ in 0..100 {
+            captured = [read_lines(&rotated), read_lines(&path)].concat();
+            if captured.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        std::fs::remove_file(&path).ok();
+        std::fs::remove_file(&rotated).ok();
+
+        // The oldest file was deleted on the second rotation
+        let payloads: Vec<u8> = captured.iter().map(|a| a.payload[1]).collect();
+        assert_eq!(payloads, vec![2, 3, 4]);
+    }
+}
@@ -0,0 +1,376 @@
+/// Ingestion of advertisements relayed by a Ruuvi Gateway over HTTP
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::Router;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+use super::ReadingSource;
+use crate::bluetooth::scanner::extract_ruuvi_manufacturer_data;
+use crate::models::Advertisement;
+use crate::utils::parse_hex;
+
+const ADVERTISEMENT_CHANNEL_CAPACITY: usize = 1024; // Advertisements buffered between HTTP server and main loop
+
+/// Body of a Ruuvi Gateway HTTP POST
+#[derive(Debug, Deserialize)]
+struct GatewayMessage {
+    data: GatewayData,
+}
+
+/// Gateway information and the advertisements it relays
+#[derive(Debug, Deserialize)]
+struct GatewayData {
+    /// MAC address of the gateway
+    gw_mac: Option<String>,
+    /// Latest advertisement per tag, keyed by BLE address
+    #[serde(default)]
+    tags: HashMap<String, GatewayTag>,
+}
+
+/// A single relayed advertisement
+#[derive(Debug, Deserialize)]
+struct GatewayTag {
+    rssi: Option<i16>,
+    /// Unix time of reception, sent as string or number depending on firmware
+    timestamp: Option<serde_json::Value>,
+    /// Complete raw advertisement as hex
+    data: String,
+}
+
+/// State shared with the HTTP request handler
+#[derive(Clone)]
+struct GatewayState {
+    tx: mpsc::Sender<Advertisement>,
+    /// Bearer token required in the Authorization header, None to accept any request
+    token: Option<Arc<str>>,
+    requests: Arc<AtomicU64>,
+    rejected: Arc<AtomicU64>,
+}
+
+/// Reading source fed by Ruuvi Gateways POSTing to an embedded HTTP server
+///
+/// The gateways must be configured to send to http://<host>:<port>/ with any
+/// path, using bearer token authentication if a token is configured. Every
+/// tag in a POST becomes one advertisement, with the gateway's MAC address
+/// recorded as the receiving adapter.
+pub struct GatewaySource {
+    advertisements: mpsc::Receiver<Advertisement>,
+    requests: Arc<AtomicU64>,
+    rejected: Arc<AtomicU64>,
+}
+
+impl GatewaySource {
+    /// Start the HTTP server on the given address
+    ///
+    /// # Arguments
+    /// * `listen` - Socket address to listen on, e.g. 127.0.0.1:8080
+    /// * `token` - Bearer token gateways must send, None to accept unauthenticated posts
+    ///
+    /// # Returns
+    /// The source, or error if the address cannot be bound
+    pub async fn bind(listen: &str, token: Option<String>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(listen).await?;
+        if token.is_none() && !listener.local_addr()?.ip().is_loopback() {
+            warn!(
+                "Ruuvi Gateway server on {} accepts posts from anyone, set GATEWAY_TOKEN to require authentication",
+                listen
+            );
+        }
+
+        let (tx, advertisements) = mpsc::channel(ADVERTISEMENT_CHANNEL_CAPACITY);
+        let state = GatewayState {
+            tx,
+            token: token.map(Arc::from),
+            requests: Arc::new(AtomicU64::new(0)),
+            rejected: Arc::new(AtomicU64::new(0)),
+        };
+        let source = GatewaySource {
+            advertisements,
+            requests: state.requests.clone(),
+            rejected: state.rejected.clone(),
+        };
+
+        let app = router(state);
+        info!("Listening for Ruuvi Gateway data on {}", listen);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                error!("Ruuvi Gateway HTTP server failed: {}", e);
+            }
+        });
+
+        Ok(source)
+    }
+}
+
+impl ReadingSource for GatewaySource {
+    async fn next_advertisement(&mut self) -> Option<Advertisement> {
+        self.advertisements.recv().await
+    }
+
+    fn log_health(&self) {
+        info!(
+            "Ruuvi Gateway: {} requests received, {} rejected",
+            self.requests.load(Ordering::Relaxed),
+            self.rejected.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// Route every request, whatever its path, to the gateway handler
+fn router(state: GatewayState) -> Router {
+    Router::new().fallback(handle_request).with_state(state)
+}
+
+/// Handle a POST from a Ruuvi Gateway
+async fn handle_request(
+    State(state): State<GatewayState>,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    if method != Method::POST {
+        return StatusCode::METHOD_NOT_ALLOWED;
+    }
+    state.requests.fetch_add(1, Ordering::Relaxed);
+
+    if let Some(token) = &state.token {
+        if !is_authorized(&headers, token) {
+            warn!("Rejected Ruuvi Gateway message without a valid bearer token");
+            state.rejected.fetch_add(1, Ordering::Relaxed);
+            return StatusCode::UNAUTHORIZED;
+        }
+    }
+
+    let message: GatewayMessage = match serde_json::from_slice(&body) {
+        Ok(message) => message,
+        Err(e) => {
+            warn!("Rejected invalid Ruuvi Gateway message: {}", e);
+            state.rejected.fetch_add(1, Ordering::Relaxed);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    let gateway = message.data.gw_mac.unwrap_or_else(|| "gateway".to_string());
+
+    for (address, tag) in message.data.tags {
+        let Some(advertisement) = relayed_advertisement(
+            &gateway,
+            &address,
+            tag.rssi,
+            tag.timestamp.as_ref(),
+            &tag.data,
+        ) else {
+            continue;
+        };
+        if state.tx.send(advertisement).await.is_err() {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+    }
+
+    StatusCode::OK
+}
+
+/// Check that a request carries the configured bearer token
+///
+/// The token is compared in constant time so that it cannot be guessed from
+/// response timings.
+fn is_authorized(headers: &HeaderMap, token: &str) -> bool {
+    let Some(presented) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    presented.len() == token.len() && openssl::memcmp::eq(presented.as_bytes(), token.as_bytes())
+}
+
+/// Convert an advertisement relayed by a gateway into the internal form
+///
+/// Shared by the HTTP and MQTT sources, which relay the same fields.
+///
+/// # Arguments
+/// * `gateway` - Name or MAC address of the relaying gateway, recorded as adapter
+/// * `address` - BLE address of the tag
+/// * `rssi` - Signal strength measured by the gateway
+/// * `timestamp` - Unix time of reception as string or number, None for now
+/// * `data` - Complete raw advertisement as hex
+///
+/// # Returns
+/// The advertisement, or None if the data is not a valid Ruuvi advertisement
+pub(super) fn relayed_advertisement(
+    gateway: &str,
+    address: &str,
+    rssi: Option<i16>,
+    timestamp: Option<&serde_json::Value>,
+    data: &str,
+) -> Option<Advertisement> {
+    let Some(raw) = parse_hex(data) else {
+        debug!("Invalid advertisement hex from {} via {}", address, gateway);
+        return None;
+    };
+    // Gateways may relay other devices too
+    let payload = extract_ruuvi_manufacturer_data(&raw)?;
+
+    Some(Advertisement {
+        received_at: timestamp
+            .and_then(parse_timestamp)
+            .unwrap_or_else(OffsetDateTime::now_utc),
+        adapter: gateway.to_uppercase(),
+        address: address.to_uppercase(),
+        random_address: false,
+        rssi,
+        payload: payload.to_vec(),
+    })
+}
+
+/// Parse a gateway timestamp given as Unix seconds in a string or number
+fn parse_timestamp(value: &serde_json::Value) -> Option<OffsetDateTime> {
+    let seconds = match value {
+        serde_json::Value::String(s) => s.parse().ok()?,
+        serde_json::Value::Number(n) => n.as_i64()?,
+        _ => return None,
+    };
+    OffsetDateTime::from_unix_timestamp(seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{HeaderValue, Request};
+    use tower::ServiceExt;
+
+    // Ruuvi Gateway POST relaying a format 5 advertisement (the published valid test vector)
+    const GATEWAY_POST: &str = r#"{
+        "data": {
+            "coordinates": "",
+            "timestamp": "1636022818",
+            "nonce": "1960243936",
+            "gw_mac": "c8:25:2d:8e:9c:2c",
+            "tags": {
+                "f4:10:d6:d8:8a:2a": {
+                    "rssi": -68,
+                    "timestamp": "1636022817",
+                    "data": "0201061BFF99040512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F"
+                },
+                "E2:58:B8:1D:4B:36": {
+                    "rssi": -80,
+                    "timestamp": 1636022816,
+                    "data": "0201061AFF4C000215B9407F30F5F8466EAFF925556B57FE6D00010002C5"
+                }
+            }
+        }
+    }"#;
+
+    fn state(token: Option<&str>) -> (GatewayState, mpsc::Receiver<Advertisement>) {
+        let (tx, rx) = mpsc::channel(16);
+        let state = GatewayState {
+            tx,
+            token: token.map(Arc::from),
+            requests: Arc::new(AtomicU64::new(0)),
+            rejected: Arc::new(AtomicU64::new(0)),
+        };
+        (state, rx)
+    }
+
+    fn post(authorization: Option<&str>) -> Request<Body> {
+        let mut request = Request::post("/ruuvi")
+            .body(Body::from(GATEWAY_POST))
+            .unwrap();
+        if let Some(authorization) = authorization {
+            request.headers_mut().insert(
+                header::AUTHORIZATION,
+                HeaderValue::from_str(authorization).unwrap(),
+            );
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn relays_ruuvi_tags_from_a_gateway_post() {
+        let (state, mut rx) = state(None);
+
+        let response = router(state).oneshot(post(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The iBeacon is not a Ruuvi advertisement and is skipped
+        let advertisement = rx.recv().await.unwrap();
+        assert!(rx.try_recv().is_err());
+        assert_eq!(advertisement.address, "F4:10:D6:D8:8A:2A");
+        assert_eq!(advertisement.adapter, "C8:25:2D:8E:9C:2C");
+        assert_eq!(advertisement.rssi, Some(-68));
+        assert_eq!(advertisement.received_at.unix_timestamp(), 1_636_022_817);
+        assert_eq!(
+            advertisement.payload,
+            parse_hex("0512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_without_the_token() {
+        let (state, mut rx) = state(Some("secret"));
+        let app = router(state.clone());
+
+        for authorization in [None, Some("Bearer wrong"), Some("secret")] {
+            let response = app.clone().oneshot(post(authorization)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(rx.try_recv().is_err());
+        assert_eq!(state.rejected.load(Ordering::Relaxed), 3);
+
+        let response = app
+            .clone()
+            .oneshot(post(Some("Bearer secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(rx.recv().await.is_some());
+
+        let get = Request::get("/").body(Body::empty()).unwrap();
+        let response = app.oneshot(get).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn checks_bearer_token() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        assert!(!is_authorized(&HeaderMap::new(), "secret"));
+        assert!(!is_authorized(&headers("Bearer secre"), "secret"));
+        assert!(!is_authorized(&headers("Bearer secreT"), "secret"));
+        assert!(!is_authorized(&headers("Basic secret"), "secret"));
+        assert!(is_authorized(&headers("Bearer secret"), "secret"));
+    }
+
+    #[test]
+    fn skips_invalid_relayed_data() {
+        let relay = |data: &str| relayed_advertisement("gw", "aa:bb:cc:dd:ee:ff", None, None, data);
+        assert!(relay("not hex").is_none());
+        assert!(relay("0201061AFF4C000215B9407F30F5F8466EAFF925556B57FE6D00010002C5").is_none());
+        assert!(relay("0201061BFF99040512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F").is_some());
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        let parse = |json: &str| {
+            parse_timestamp(&serde_json::from_str(json).unwrap()).map(|t| t.unix_timestamp())
+        };
+        assert_eq!(parse(r#""1636022817""#), Some(1_636_022_817));
+        assert_eq!(parse("1636022817"), Some(1_636_022_817));
+        assert_eq!(parse(r#""yesterday""#), None);
+        assert_eq!(parse("1636022817.5"), None);
+        assert_eq!(parse("null"), None);
+    }
+}
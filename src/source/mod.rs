@@ -0,0 +1,35 @@
+/// Pluggable sources of Ruuvi advertisements
+use std::future::Future;
+
+use crate::models::Advertisement;
+
+pub mod bluetooth;
+pub mod capture;
+pub mod gateway;
+pub mod mqtt;
+pub mod replay;
+pub mod simulated;
+
+pub use bluetooth::BluetoothSource;
+pub use capture::{CaptureWriter, CapturingSource};
+pub use gateway::GatewaySource;
+pub use mqtt::MqttSource;
+pub use replay::ReplaySource;
+pub use simulated::SimulatedSource;
+
+/// A source of Ruuvi advertisements for the aggregation loop
+///
+/// The main loop only depends on this trait, so the BlueZ scanner can be
+/// replaced by alternative sources such as file replay, simulated tags or
+/// network gateways.
+pub trait ReadingSource {
+    /// Wait for the next advertisement
+    ///
+    /// # Returns
+    /// The next advertisement, or None once the source is exhausted and no
+    /// more advertisements will follow
+    fn next_advertisement(&mut self) -> impl Future<Output = Option<Advertisement>> + Send;
+
+    /// Log the health of the source at the end of a collection interval
+    fn log_health(&self) {}
+}
@@ -0,0 +1,129 @@
+/// Replay of captured advertisements from JSON Lines files
+use log::{error, info, warn};
+use time::OffsetDateTime;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::time::sleep;
+
+use super::ReadingSource;
+use crate::clock::ReplayClock;
+use crate::models::Advertisement;
+
+const REPLAY_ADAPTER: &str = "replay"; // Adapter name for captures that do not record one
+
+/// Reading source that feeds advertisements from a capture file
+///
+/// Advertisements are yielded in file order and advance the replay clock to
+/// their original timestamps, so they are aggregated into the same intervals
+/// as when they were received. With a speed factor the gaps between
+/// advertisements are waited out accelerated by that factor, otherwise the
+/// file is replayed as fast as possible.
+pub struct ReplaySource {
+    lines: Lines<BufReader<File>>,
+    clock: ReplayClock,
+    /// Acceleration factor, 0 for no waiting at all
+    speed: f64,
+    /// Next advertisement, read ahead to start the clock
+    pending: Option<Advertisement>,
+    /// Timestamp of the previously yielded advertisement
+    previous: Option<OffsetDateTime>,
+    line_number: u64,
+    skipped: u64,
+}
+
+impl ReplaySource {
+    /// Open a capture file for replay
+    ///
+    /// # Arguments
+    /// * `path` - Path of the JSON Lines capture file
+    /// * `speed` - Acceleration factor for the gaps between advertisements, 0 for as fast as possible
+    ///
+    /// # Returns
+    /// The source and the clock it drives, starting at the first advertisement's timestamp,
+    /// or error if the file cannot be opened
+    pub async fn open(path: &str, speed: f64) -> std::io::Result<(Self, ReplayClock)> {
+        let file = File::open(path).await?;
+        let mut source = ReplaySource {
+            lines: BufReader::new(file).lines(),
+            clock: ReplayClock::new(OffsetDateTime::now_utc()),
+            speed,
+            pending: None,
+            previous: None,
+            line_number: 0,
+            skipped: 0,
+        };
+
+        source.pending = source.read_next().await;
+        if let Some(first) = &source.pending {
+            source.clock = ReplayClock::new(first.received_at);
+        }
+        info!("Replaying advertisements from {}", path);
+
+        let clock = source.clock.clone();
+        Ok((source, clock))
+    }
+
+    /// Read the next valid advertisement from the file, skipping invalid lines
+    async fn read_next(&mut self) -> Option<Advertisement> {
+        loop {
+            let line = match self.lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => {
+                    error!("Failed to read replay file: {}", e);
+                    return None;
+                }
+            };
+            self.line_number += 1;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Advertisement>(&line) {
+                Ok(mut advertisement) => {
+                    if advertisement.adapter.is_empty() {
+                        advertisement.adapter = REPLAY_ADAPTER.to_string();
+                    }
+                    return Some(advertisement);
+                }
+                Err(e) => {
+                    warn!(
+                        "Skipping invalid line {} in replay file: {}",
+                        self.line_number, e
+                    );
+                    self.skipped += 1;
+                }
+            }
+        }
+    }
+}
+
+impl ReadingSource for ReplaySource {
+    async fn next_advertisement(&mut self) -> Option<Advertisement> {
+        let advertisement = match self.pending.take() {
+            Some(advertisement) => advertisement,
+            None => self.read_next().await?,
+        };
+
+        // Wait out the original gap between advertisements, accelerated
+        if self.speed > 0.0 {
+            if let Some(previous) = self.previous {
+                if let Ok(gap) = std::time::Duration::try_from(advertisement.received_at - previous)
+                {
+                    sleep(gap.div_f64(self.speed)).await;
+                }
+            }
+        }
+
+        self.previous = Some(advertisement.received_at);
+        self.clock.advance_to(advertisement.received_at);
+        Some(advertisement)
+    }
+
+    fn log_health(&self) {
+        info!(
+            "Replayed {} lines, skipped {} invalid lines",
+            self.line_number, self.skipped
+        );
+    }
+}
@@ -0,0 +1,94 @@
+/// Simulated RuuviTags for development without Bluetooth hardware
+use time::OffsetDateTime;
+use tokio::time::{sleep_until, Duration, Instant};
+
+use super::ReadingSource;
+use crate::bluetooth::encoder::encode_ruuvi_data;
+use crate::models::{Advertisement, RuuviData};
+use crate::utils::parse_mac;
+
+const SIMULATED_ADAPTER: &str = "simulated"; // Adapter name recorded for simulated readings
+const SIMULATED_ADVERTISEMENT_INTERVAL_MS: u64 = 1000; // How often each simulated tag advertises
+const SIMULATED_RSSI: i16 = -60; // Signal strength reported for simulated tags
+
+/// Reading source that generates data format 5 advertisements for the configured tags
+///
+/// Every tag advertises once per second with slowly varying temperature,
+/// humidity and pressure, an occasional movement and a slowly draining
+/// battery. The payloads are produced with the format 5 encoder, so they pass
+/// through exactly the same decoding path as real advertisements.
+pub struct SimulatedSource {
+    /// MAC addresses of the simulated tags as string and bytes
+    tags: Vec<(String, Option<[u8; 6]>)>,
+    /// Number of advertisements generated so far
+    tick: u64,
+    /// Time at which the next advertisement is due
+    next_at: Instant,
+}
+
+impl SimulatedSource {
+    /// Create a simulator for the given tag MAC addresses
+    ///
+    /// # Arguments
+    /// * `macs` - MAC addresses of the tags to simulate
+    pub fn new<'a>(macs: impl Iterator<Item = &'a String>) -> Self {
+        let mut tags: Vec<(String, Option<[u8; 6]>)> =
+            macs.map(|mac| (mac.clone(), parse_mac(mac))).collect();
+        tags.sort();
+
+        SimulatedSource {
+            tags,
+            tick: 0,
+            next_at: Instant::now(),
+        }
+    }
+
+    /// Generate the sensor data of a tag at the current tick
+    fn simulate(&self, index: usize) -> RuuviData {
+        let round = self.tick / self.tags.len() as u64;
+        let phase = round as f32 / 600.0 + index as f32;
+
+        RuuviData {
+            temperature: Some(21.0 + index as f32 + 2.0 * phase.sin()),
+            humidity: Some(45.0 + 5.0 * phase.cos()),
+            pressure: Some(1005.0 + 3.0 * (phase / 2.0).sin()),
+            acceleration_x: Some(0.0),
+            acceleration_y: Some(0.0),
+            acceleration_z: Some(1.0),
+            movement_counter: Some((round / 300 % 255) as u8),
+            battery_voltage: Some(3000u16.saturating_sub((round / 3600) as u16)),
+            tx_power: Some(4),
+            measurement_sequence: Some((round % 0xFFFF) as u16),
+            rssi: None,
+            adapter: None,
+            received_at: None,
+        }
+    }
+}
+
+impl ReadingSource for SimulatedSource {
+    async fn next_advertisement(&mut self) -> Option<Advertisement> {
+        if self.tags.is_empty() {
+            return None;
+        }
+
+        // Spread the advertisements of all tags evenly over the interval
+        sleep_until(self.next_at).await;
+        self.next_at +=
+            Duration::from_millis(SIMULATED_ADVERTISEMENT_INTERVAL_MS) / self.tags.len() as u32;
+
+        let index = (self.tick % self.tags.len() as u64) as usize;
+        let data = self.simulate(index);
+        let (address, mac) = &self.tags[index];
+        self.tick += 1;
+
+        Some(Advertisement {
+            received_at: OffsetDateTime::now_utc(),
+            adapter: SIMULATED_ADAPTER.to_string(),
+            address: address.clone(),
+            random_address: false,
+            rssi: Some(SIMULATED_RSSI),
+            payload: encode_ruuvi_data(&data, *mac).to_vec(),
+        })
+    }
+}
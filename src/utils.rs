@@ -1,9 +1,12 @@
 /// Utility functions for data processing and formatting
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use time::{format_description, OffsetDateTime};
 
 use crate::config::SensorConfig;
-use crate::models::{AverageData, RuuviData};
+use crate::models::{
+    AirQualityData, AverageAirQualityData, AverageData, MetricStats, RuuviData, SensorReading,
+    WindowBounds,
+};
 
 /// Format a timestamp for human-readable logging
 ///
@@ -15,11 +18,525 @@ pub fn format_datetime(dt: &OffsetDateTime) -> String {
     dt.format(&format).unwrap_or_else(|_| dt.to_string())
 }
 
-/// Convert a time::Duration to seconds as u64
+/// Format an optional metric for human-readable logging
 ///
-/// Helper function to work with duration calculations in the main loop.
-pub fn duration_to_seconds(duration: time::Duration) -> u64 {
-    duration.whole_seconds() as u64
+/// Uses the given number of decimal places, or "n/a" if the metric is missing.
+pub fn format_optional<T: Into<f64>>(value: Option<T>, decimals: usize) -> String {
+    match value {
+        Some(value) => format!("{:.*}", decimals, value.into()),
+        None => "n/a".to_string(),
+    }
+}
+
+/// Format the distribution of a metric for human-readable logging
+///
+/// Produces e.g. "min=20.10, max=22.40, sd=0.52, median=21.30",
+/// or "n/a" if the metric is missing.
+pub fn format_stats(stats: Option<&MetricStats>, decimals: usize) -> String {
+    match stats {
+        Some(stats) => format!(
+            "min={:.*}, max={:.*}, sd={:.*}, median={:.*}",
+            decimals,
+            stats.min,
+            decimals,
+            stats.max,
+            decimals,
+            stats.std_dev,
+            decimals,
+            stats.median
+        ),
+        None => "n/a".to_string(),
+    }
+}
+
+/// Format an aggregation window length for human-readable logging
+///
+/// Uses the largest whole unit, e.g. "30m" for 1800 seconds or "1d" for 86400.
+pub fn format_resolution(seconds: u64) -> String {
+    match seconds {
+        s if s > 0 && s.is_multiple_of(86400) => format!("{}d", s / 86400),
+        s if s > 0 && s.is_multiple_of(3600) => format!("{}h", s / 3600),
+        s if s > 0 && s.is_multiple_of(60) => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// Format per-class counters for human-readable logging
+///
+/// Produces e.g. "crc_mismatch=2, invalid_length=1" sorted by class name,
+/// or "none" if nothing was counted.
+pub fn format_counts<K: Ord + std::fmt::Display>(counts: Option<&HashMap<K, u32>>) -> String {
+    let mut entries: Vec<_> = counts.into_iter().flatten().collect();
+    if entries.is_empty() {
+        return "none".to_string();
+    }
+    entries.sort();
+    entries
+        .iter()
+        .map(|(kind, count)| format!("{}={}", kind, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Describe a single decoded reading for human-readable logging
+///
+/// Produces e.g. "temperature=21.50°C, humidity=45.20%, pressure=1002.10 hPa, battery=2977 mV".
+pub fn describe_reading(reading: &SensorReading) -> String {
+    match reading {
+        SensorReading::Tag(data) => format!(
+            "temperature={}°C, humidity={}%, pressure={} hPa, battery={} mV",
+            format_optional(data.temperature, 2),
+            format_optional(data.humidity, 2),
+            format_optional(data.pressure, 2),
+            format_optional(data.battery_voltage, 0)
+        ),
+        SensorReading::AirQuality(data) => format!(
+            "temperature={}°C, humidity={}%, co2={} ppm, pm2.5={} µg/m³",
+            format_optional(data.temperature, 2),
+            format_optional(data.humidity, 2),
+            format_optional(data.co2, 0),
+            format_optional(data.pm2_5, 1)
+        ),
+    }
+}
+
+/// Serialize bytes as a lowercase hexadecimal string
+///
+/// Used for raw advertisement payloads in capture files.
+pub fn serialize_hex<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    serializer.serialize_str(&hex)
+}
+
+/// Deserialize bytes from a hexadecimal string
+///
+/// Used for raw advertisement payloads in capture files.
+pub fn deserialize_hex<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<u8>, D::Error> {
+    let hex = <String as serde::Deserialize>::deserialize(deserializer)?;
+    parse_hex(&hex).ok_or_else(|| serde::de::Error::custom("invalid hex string"))
+}
+
+/// Parse bytes from a string of hexadecimal digits (either case)
+pub fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parse a colon-separated MAC address such as "AA:BB:CC:DD:EE:FF" (either case)
+pub fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mut bytes = [0u8; 6];
+    let mut parts = mac.split(':');
+    for byte in bytes.iter_mut() {
+        let part = parts.next().filter(|part| part.len() == 2)?;
+        *byte = parse_hex(part)?[0];
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(bytes)
+}
+
+/// Count readings per receiving Bluetooth adapter
+///
+/// Readings without a recorded adapter are counted as "unknown".
+pub fn count_adapters<'a>(
+    adapters: impl Iterator<Item = Option<&'a str>>,
+) -> HashMap<&'a str, u32> {
+    let mut counts = HashMap::new();
+    for adapter in adapters {
+        *counts.entry(adapter.unwrap_or("unknown")).or_default() += 1;
+    }
+    counts
+}
+
+/// Number of recent measurement sequence numbers remembered per sensor
+///
+/// Covers readings arriving out of order from several adapters or gateways
+/// while staying far below the 256 values of the 8-bit Ruuvi Air counter.
+const RECENT_SEQUENCES: usize = 16;
+
+/// Tracks the recent measurement sequence numbers seen from each sensor
+///
+/// BlueZ keeps serving a device's last cached advertisement after the device
+/// goes out of range, so a reading whose sequence number was already seen is
+/// a stale duplicate rather than a new measurement. Several sequence numbers
+/// are remembered per sensor so that a copy of an earlier measurement relayed
+/// late by another adapter is recognised as well. The tracker lives across
+/// collection intervals.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    recent_sequences: HashMap<String, VecDeque<u32>>,
+}
+
+impl SequenceTracker {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether a reading is a genuinely new measurement
+    ///
+    /// Records the sequence number and returns false if it is one of the recent
+    /// ones seen from the sensor. Ruuvi Air alternates between format 6, which
+    /// carries only the lowest 8 bits of the sequence number, and format E1,
+    /// which carries 24 bits, so only the lowest 8 bits are compared for Air
+    /// readings. Readings without a sequence number (data format 3) cannot be
+    /// checked and are always treated as new.
+    pub fn is_new(&mut self, sensor_id: &str, reading: &SensorReading) -> bool {
+        let sequence = match reading {
+            SensorReading::Tag(_) => reading.measurement_sequence(),
+            SensorReading::AirQuality(_) => reading.measurement_sequence().map(|s| s & 0xFF),
+        };
+        let Some(sequence) = sequence else {
+            return true;
+        };
+
+        let recent = self
+            .recent_sequences
+            .entry(sensor_id.to_string())
+            .or_default();
+        if recent.contains(&sequence) {
+            return false;
+        }
+        if recent.len() == RECENT_SEQUENCES {
+            recent.pop_front();
+        }
+        recent.push_back(sequence);
+        true
+    }
+}
+
+/// Calculate the average of the values that are present
+///
+/// Readings that do not carry a metric are skipped instead of being counted
+/// as zero. Returns None if no reading carried the metric.
+pub fn average_present(values: impl Iterator<Item = Option<f32>>) -> Option<f32> {
+    let (sum, count) = values
+        .flatten()
+        .fold((0.0f32, 0usize), |(sum, count), value| {
+            (sum + value, count + 1)
+        });
+    if count == 0 {
+        None
+    } else {
+        Some(sum / count as f32)
+    }
+}
+
+/// Longest gap between consecutive readings that is interpolated over (seconds)
+///
+/// RuuviTags advertise every few seconds, so a longer gap means the tag was out
+/// of range or the service was not receiving. A reading stands for at most half
+/// of this on either side in time-weighted means, and longer gaps are not
+/// counted as covered.
+const MAX_SAMPLE_GAP_SECS: f64 = 60.0;
+
+/// Calculate the time-weighted average of the values that are present
+///
+/// Each reading is weighted by the time it stands for: half the gap to the
+/// previous reading plus half the gap to the next, each half capped at
+/// `MAX_SAMPLE_GAP_SECS / 2`. A burst of readings after an outage therefore
+/// does not outweigh evenly spread readings, and readings far apart from
+/// their neighbours are not dropped. Falls back to the plain average if all
+/// readings share the same receive time. Returns None if no reading carried
+/// the metric.
+///
+/// # Arguments
+/// * `samples` - Receive time and value of each reading
+pub fn time_weighted_average(
+    samples: impl Iterator<Item = (Option<OffsetDateTime>, Option<f32>)>,
+) -> Option<f32> {
+    let mut samples: Vec<(OffsetDateTime, f32)> = samples
+        .filter_map(|(time, value)| Some((time?, value?)))
+        .collect();
+    samples.sort_by_key(|(time, _)| *time);
+
+    // Each gap between consecutive readings is split between the two
+    let half_gaps: Vec<f64> = samples
+        .windows(2)
+        .map(|pair| ((pair[1].0 - pair[0].0).as_seconds_f64() / 2.0).min(MAX_SAMPLE_GAP_SECS / 2.0))
+        .collect();
+
+    let (mut area, mut duration) = (0.0f64, 0.0f64);
+    for (index, (_, value)) in samples.iter().enumerate() {
+        let before = index
+            .checked_sub(1)
+            .map_or(0.0, |previous| half_gaps[previous]);
+        let after = half_gaps.get(index).copied().unwrap_or(0.0);
+        area += *value as f64 * (before + after);
+        duration += before + after;
+    }
+
+    if duration > 0.0 {
+        Some((area / duration) as f32)
+    } else {
+        average_present(samples.iter().map(|(_, value)| Some(*value)))
+    }
+}
+
+/// Calculate the fraction of a window covered by readings
+///
+/// The time between consecutive readings counts as covered unless it exceeds
+/// `MAX_SAMPLE_GAP_SECS`. Only the part within the window is counted.
+///
+/// # Arguments
+/// * `times` - Receive times of the readings
+/// * `window` - Bounds of the aggregation window
+///
+/// # Returns
+/// Coverage ratio between 0.0 and 1.0
+pub fn window_coverage(
+    times: impl Iterator<Item = Option<OffsetDateTime>>,
+    window: &WindowBounds,
+) -> f32 {
+    let mut times: Vec<OffsetDateTime> = times.flatten().collect();
+    times.sort();
+
+    let length = (window.end - window.start).as_seconds_f64();
+    if length <= 0.0 {
+        return 0.0;
+    }
+    let covered: f64 = times
+        .windows(2)
+        .filter(|pair| (pair[1] - pair[0]).as_seconds_f64() <= MAX_SAMPLE_GAP_SECS)
+        .map(|pair| {
+            let (from, to) = (pair[0].max(window.start), pair[1].min(window.end));
+            (to - from).as_seconds_f64().max(0.0)
+        })
+        .sum();
+    (covered / length).clamp(0.0, 1.0) as f32
+}
+
+/// Calculate the minimum, maximum, standard deviation and median of the values that are present
+///
+/// Like `average_present`, readings that do not carry the metric are skipped.
+/// Returns None if no reading carried the metric.
+pub fn metric_stats(values: impl Iterator<Item = Option<f32>>) -> Option<MetricStats> {
+    let mut values: Vec<f32> = values.flatten().collect();
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f32::total_cmp);
+
+    let count = values.len();
+    let mean = values.iter().sum::<f32>() / count as f32;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / count as f32;
+
+    Some(MetricStats {
+        min: values[0],
+        max: values[count - 1],
+        std_dev: variance.sqrt(),
+        median: median_of_sorted(&values),
+    })
+}
+
+/// Median of a non-empty, sorted slice
+fn median_of_sorted(values: &[f32]) -> f32 {
+    let count = values.len();
+    if count.is_multiple_of(2) {
+        (values[count / 2 - 1] + values[count / 2]) / 2.0
+    } else {
+        values[count / 2]
+    }
+}
+
+/// Physical limits of a RuuviTag metric used for outlier rejection
+///
+/// Values are in the unit of the metric (°C, %, hPa or g).
+struct MetricLimits {
+    /// Name of the metric in logs
+    name: &'static str,
+    /// Value of the metric in a reading
+    value: fn(&RuuviData) -> Option<f32>,
+    /// Lowest and highest value the sensor can measure
+    min: f32,
+    max: f32,
+    /// Smallest deviation worth rejecting, so that a steady metric (zero MAD)
+    /// does not turn every change of the last digit into an outlier
+    min_spread: f32,
+    /// Whether the metric is stored in sensor_data rather than movement_data
+    atmospheric: bool,
+}
+
+// Limits of the RuuviTag metrics checked by the outlier filter
+const FILTERED_METRICS: [MetricLimits; 6] = [
+    MetricLimits {
+        name: "temperature",
+        value: |reading| reading.temperature,
+        min: -40.0,
+        max: 125.0,
+        min_spread: 0.2,
+        atmospheric: true,
+    },
+    MetricLimits {
+        name: "humidity",
+        value: |reading| reading.humidity,
+        min: 0.0,
+        max: 100.0,
+        min_spread: 1.0,
+        atmospheric: true,
+    },
+    MetricLimits {
+        name: "pressure",
+        value: |reading| reading.pressure,
+        min: 300.0,
+        max: 1100.0,
+        min_spread: 0.5,
+        atmospheric: true,
+    },
+    MetricLimits {
+        name: "acceleration_x",
+        value: |reading| reading.acceleration_x,
+        min: -16.0,
+        max: 16.0,
+        min_spread: 0.05,
+        atmospheric: false,
+    },
+    MetricLimits {
+        name: "acceleration_y",
+        value: |reading| reading.acceleration_y,
+        min: -16.0,
+        max: 16.0,
+        min_spread: 0.05,
+        atmospheric: false,
+    },
+    MetricLimits {
+        name: "acceleration_z",
+        value: |reading| reading.acceleration_z,
+        min: -16.0,
+        max: 16.0,
+        min_spread: 0.05,
+        atmospheric: false,
+    },
+];
+const HAMPEL_HALF_WINDOW: usize = 5; // Neighbouring readings on each side compared against
+const MAD_TO_STD_DEV: f32 = 1.4826; // Scales MAD to standard deviation for normal data
+
+/// Number of outlying values removed from a sensor's readings
+#[derive(Debug, Default)]
+pub struct RejectedOutliers {
+    /// Rejected values per metric
+    pub per_metric: HashMap<&'static str, u32>,
+    /// Readings with a rejected temperature, humidity or pressure
+    pub atmospheric: i32,
+    /// Readings with a rejected acceleration
+    pub movement: i32,
+}
+
+/// Values of the filtered metrics in a sensor's readings, in the order of
+/// `FILTERED_METRICS`: temperature, humidity, pressure and acceleration X, Y and Z
+pub type MetricValues = [Vec<Option<f32>>; FILTERED_METRICS.len()];
+
+/// Extract the values of the filtered metrics from a sensor's readings
+pub fn metric_values(readings: &[RuuviData]) -> MetricValues {
+    FILTERED_METRICS
+        .each_ref()
+        .map(|limits| readings.iter().map(limits.value).collect())
+}
+
+/// Remove physically impossible and outlying values from a sensor's readings
+///
+/// Values outside the sensor's measurement range are rejected first. The
+/// remaining values are then passed through a Hampel filter: a value is
+/// rejected if it deviates from the median of its neighbouring readings by
+/// more than `hampel_threshold` times the scaled median absolute deviation
+/// (MAD). Rejected values are set to None so that they are left out of the
+/// averages and statistics like missing values. The readings themselves are
+/// left untouched, as they are shared by all aggregation windows.
+///
+/// # Arguments
+/// * `readings` - Readings of a single sensor in the order they were received
+/// * `hampel_threshold` - Deviation limit in standard deviations, 0 to only check ranges
+///
+/// # Returns
+/// Values of the filtered metrics with outliers removed, and the number of values rejected
+pub fn reject_outliers(
+    readings: &[RuuviData],
+    hampel_threshold: f32,
+) -> (MetricValues, RejectedOutliers) {
+    let mut metrics = metric_values(readings);
+    let mut rejected = RejectedOutliers::default();
+    let mut atmospheric = vec![false; readings.len()];
+    let mut movement = vec![false; readings.len()];
+
+    for (limits, values) in FILTERED_METRICS.iter().zip(metrics.iter_mut()) {
+        // Range check against the sensor's measurement range
+        let mut outliers: Vec<bool> = values
+            .iter()
+            .map(|value| value.is_some_and(|v| v < limits.min || v > limits.max))
+            .collect();
+        for (value, outlier) in values.iter_mut().zip(&outliers) {
+            if *outlier {
+                *value = None;
+            }
+        }
+
+        // Hampel filter over the values within range
+        if hampel_threshold > 0.0 {
+            let hampel = hampel_outliers(values, hampel_threshold, limits.min_spread);
+            for (outlier, hampel_outlier) in outliers.iter_mut().zip(hampel) {
+                *outlier |= hampel_outlier;
+            }
+        }
+
+        for (index, value) in values.iter_mut().enumerate() {
+            if !outliers[index] {
+                continue;
+            }
+            *value = None;
+            *rejected.per_metric.entry(limits.name).or_default() += 1;
+            if limits.atmospheric {
+                atmospheric[index] = true;
+            } else {
+                movement[index] = true;
+            }
+        }
+    }
+
+    rejected.atmospheric = atmospheric.iter().filter(|r| **r).count() as i32;
+    rejected.movement = movement.iter().filter(|r| **r).count() as i32;
+    (metrics, rejected)
+}
+
+/// Find the values that a Hampel filter marks as outliers
+///
+/// Each present value is compared against the median and MAD of the present
+/// values up to `HAMPEL_HALF_WINDOW` readings before and after it. Missing
+/// values are never outliers.
+fn hampel_outliers(values: &[Option<f32>], threshold: f32, min_spread: f32) -> Vec<bool> {
+    let present: Vec<(usize, f32)> = values
+        .iter()
+        .enumerate()
+        .filter_map(|(index, value)| value.map(|v| (index, v)))
+        .collect();
+    let mut outliers = vec![false; values.len()];
+
+    for (position, &(index, value)) in present.iter().enumerate() {
+        let from = position.saturating_sub(HAMPEL_HALF_WINDOW);
+        let to = (position + HAMPEL_HALF_WINDOW + 1).min(present.len());
+        // Too few neighbours to tell which value is the outlier
+        if to - from < 3 {
+            continue;
+        }
+
+        let mut neighbourhood: Vec<f32> = present[from..to].iter().map(|(_, v)| *v).collect();
+        neighbourhood.sort_by(f32::total_cmp);
+        let median = median_of_sorted(&neighbourhood);
+        let mut deviations: Vec<f32> = neighbourhood.iter().map(|v| (v - median).abs()).collect();
+        deviations.sort_by(f32::total_cmp);
+        let spread = (MAD_TO_STD_DEV * median_of_sorted(&deviations)).max(min_spread);
+
+        outliers[index] = (value - median).abs() > threshold * spread;
+    }
+
+    outliers
 }
 
 /// Calculate average values from collected sensor measurements
@@ -29,14 +546,16 @@ pub fn duration_to_seconds(duration: time::Duration) -> u64 {
 /// empty data sets and wrapping movement counters.
 ///
 /// # Arguments
-/// * `measurements` - HashMap mapping sensor MAC addresses to vectors of readings
+/// * `measurements` - HashMap mapping sensor MAC addresses to readings sorted by receive time
 /// * `config` - Configuration containing sensor name mappings
+/// * `window` - Bounds of the aggregation window the readings were collected in
 ///
 /// # Returns
 /// HashMap mapping sensor MAC addresses to calculated averages
 pub fn calculate_averages(
-    measurements: &HashMap<String, Vec<RuuviData>>,
+    measurements: &HashMap<&str, &[RuuviData]>,
     config: &SensorConfig,
+    window: &WindowBounds,
 ) -> HashMap<String, AverageData> {
     let mut averages = HashMap::new();
 
@@ -46,50 +565,354 @@ pub fn calculate_averages(
             continue;
         }
 
-        let count = data_points.len() as f32;
+        // Reject physically impossible and outlying values before averaging, since
+        // RF-corrupted frames can pass the format checks with absurd values
+        let (metrics, rejected) = if config.outlier_filter {
+            reject_outliers(data_points, config.hampel_threshold)
+        } else {
+            (metric_values(data_points), RejectedOutliers::default())
+        };
+        let [temperature, humidity, pressure, acceleration_x, acceleration_y, acceleration_z] =
+            &metrics;
+
+        // Calculate time-weighted averages for atmospheric data over the readings with a valid value
+        let weighted = |values: &[Option<f32>]| {
+            time_weighted_average(
+                data_points
+                    .iter()
+                    .map(|d| d.received_at)
+                    .zip(values.iter().copied()),
+            )
+        };
+        let temp_avg = weighted(temperature);
+        let humid_avg = weighted(humidity);
+        let press_avg = weighted(pressure);
 
-        // Calculate sums for atmospheric data
-        let temp_sum: f32 = data_points.iter().map(|d| d.temperature).sum();
-        let humid_sum: f32 = data_points.iter().map(|d| d.humidity).sum();
-        let press_sum: f32 = data_points.iter().map(|d| d.pressure).sum();
+        // Calculate averages for acceleration data over the readings that carry it
+        // (encrypted data format 8 does not include acceleration)
+        let acc_x_avg = weighted(acceleration_x);
+        let acc_y_avg = weighted(acceleration_y);
+        let acc_z_avg = weighted(acceleration_z);
 
-        // Calculate sums for acceleration data
-        let acc_x_sum: f32 = data_points.iter().map(|d| d.acceleration_x).sum();
-        let acc_y_sum: f32 = data_points.iter().map(|d| d.acceleration_y).sum();
-        let acc_z_sum: f32 = data_points.iter().map(|d| d.acceleration_z).sum();
+        // Distribution of the same metrics, so that extremes are not hidden by the mean
+        let temp_stats = metric_stats(temperature.iter().copied());
+        let humid_stats = metric_stats(humidity.iter().copied());
+        let press_stats = metric_stats(pressure.iter().copied());
+        let acc_x_stats = metric_stats(acceleration_x.iter().copied());
+        let acc_y_stats = metric_stats(acceleration_y.iter().copied());
+        let acc_z_stats = metric_stats(acceleration_z.iter().copied());
 
         // Calculate movement counter delta (handles wrapping)
         // Movement counter increases when the sensor flips
         // We want the total movement during the collection interval
-        let movement_delta = data_points
-            .last()
-            .and_then(|last| {
-                data_points
-                    .first()
-                    .map(|first| last.movement_counter.wrapping_sub(first.movement_counter) as u32)
-            })
-            .unwrap_or(0);
+        // Sensors using data format 3 do not report movement, in which case
+        // the delta is left empty
+        let movement_counters: Vec<u8> = data_points
+            .iter()
+            .filter_map(|d| d.movement_counter)
+            .collect();
+        let movement_delta = movement_counters.last().and_then(|last| {
+            movement_counters
+                .first()
+                .map(|first| last.wrapping_sub(*first) as u32)
+        });
+
+        // Battery voltage is tracked as the minimum over the interval, since the
+        // lowest reading is the best indicator of a depleting battery
+        let battery_voltage = data_points.iter().filter_map(|d| d.battery_voltage).min();
+
+        // TX power is a configuration value, so the latest reading is reported
+        let tx_power = data_points.iter().rev().find_map(|d| d.tx_power);
+
+        // Signal strength statistics help to tell range problems from dying batteries
+        let rssi_min = data_points.iter().filter_map(|d| d.rssi).min();
+        let rssi_max = data_points.iter().filter_map(|d| d.rssi).max();
+        let rssi_avg = average_present(data_points.iter().map(|d| d.rssi.map(f32::from)));
+
+        // Fraction of the window with readings, so that averages based on a short
+        // burst of data can be told apart from averages over the whole window
+        let coverage = window_coverage(data_points.iter().map(|d| d.received_at), window);
 
         // Create averaged data with proper rounding
         let avg_data = AverageData {
-            temperature: (temp_sum / count * 100.0).round() / 100.0, // 2 decimal places
-            humidity: (humid_sum / count * 100.0).round() / 100.0,   // 2 decimal places
-            pressure: (press_sum / count * 100.0).round() / 100.0,   // 2 decimal places
-            acceleration_x: (acc_x_sum / count * 1000.0).round() / 1000.0, // 3 decimal places
-            acceleration_y: (acc_y_sum / count * 1000.0).round() / 1000.0, // 3 decimal places
-            acceleration_z: (acc_z_sum / count * 1000.0).round() / 1000.0, // 3 decimal places
+            temperature: temp_avg.map(|v| (v * 100.0).round() / 100.0), // 2 decimal places
+            humidity: humid_avg.map(|v| (v * 100.0).round() / 100.0),   // 2 decimal places
+            pressure: press_avg.map(|v| (v * 100.0).round() / 100.0),   // 2 decimal places
+            acceleration_x: acc_x_avg.map(|v| (v * 1000.0).round() / 1000.0), // 3 decimal places
+            acceleration_y: acc_y_avg.map(|v| (v * 1000.0).round() / 1000.0), // 3 decimal places
+            acceleration_z: acc_z_avg.map(|v| (v * 1000.0).round() / 1000.0), // 3 decimal places
+            temperature_stats: temp_stats.map(|s| s.rounded(2)),
+            humidity_stats: humid_stats.map(|s| s.rounded(2)),
+            pressure_stats: press_stats.map(|s| s.rounded(2)),
+            acceleration_x_stats: acc_x_stats.map(|s| s.rounded(3)),
+            acceleration_y_stats: acc_y_stats.map(|s| s.rounded(3)),
+            acceleration_z_stats: acc_z_stats.map(|s| s.rounded(3)),
             movement_counter: movement_delta,
-            time: OffsetDateTime::now_utc(),
+            battery_voltage,
+            tx_power,
+            rssi_min,
+            rssi_avg: rssi_avg.map(|v| (v * 10.0).round() / 10.0), // 1 decimal place
+            rssi_max,
+            time: window.end,
             name: config
                 .tags
-                .get(sensor_id)
+                .get(*sensor_id)
                 .cloned()
                 .unwrap_or_else(|| "Unknown".to_string()),
             samples: data_points.len() as i32,
+            rejected_atmospheric: rejected.atmospheric,
+            rejected_movement: rejected.movement,
+            rejected: rejected.per_metric,
+            resolution: window.resolution as i32,
+            window_start: window.start,
+            partial: window.partial,
+            coverage: (coverage * 1000.0).round() / 1000.0, // 3 decimal places
         };
 
-        averages.insert(sensor_id.clone(), avg_data);
+        averages.insert(sensor_id.to_string(), avg_data);
     }
 
     averages
 }
+
+/// Calculate average values from collected air quality measurements
+///
+/// Takes a collection of Ruuvi Air readings grouped by sensor ID and produces
+/// averaged data suitable for database storage. Metrics that were not reported
+/// by any reading in the interval, such as the particulate matter sizes only
+/// carried by format E1, are left empty.
+///
+/// # Arguments
+/// * `measurements` - HashMap mapping sensor MAC addresses to readings sorted by receive time
+/// * `config` - Configuration containing sensor name mappings
+/// * `window` - Bounds of the aggregation window the readings were collected in
+///
+/// # Returns
+/// HashMap mapping sensor MAC addresses to calculated averages
+pub fn calculate_air_quality_averages(
+    measurements: &HashMap<&str, &[AirQualityData]>,
+    config: &SensorConfig,
+    window: &WindowBounds,
+) -> HashMap<String, AverageAirQualityData> {
+    let mut averages = HashMap::new();
+
+    for (sensor_id, data_points) in measurements {
+        // Skip sensors with no data
+        if data_points.is_empty() {
+            continue;
+        }
+
+        // Time-weighted average over the readings that carry a valid value for a metric
+        let weighted = |value: fn(&AirQualityData) -> Option<f32>| {
+            time_weighted_average(data_points.iter().map(|d| (d.received_at, value(d))))
+        };
+        let temp_avg = weighted(|d| d.temperature);
+        let humid_avg = weighted(|d| d.humidity);
+        let press_avg = weighted(|d| d.pressure);
+        let pm1_0_avg = weighted(|d| d.pm1_0);
+        let pm2_5_avg = weighted(|d| d.pm2_5);
+        let pm4_0_avg = weighted(|d| d.pm4_0);
+        let pm10_0_avg = weighted(|d| d.pm10_0);
+        let co2_avg = weighted(|d| d.co2.map(f32::from));
+        let voc_avg = weighted(|d| d.voc_index.map(f32::from));
+        let nox_avg = weighted(|d| d.nox_index.map(f32::from));
+        let lum_avg = weighted(|d| d.luminosity);
+
+        let coverage = window_coverage(data_points.iter().map(|d| d.received_at), window);
+
+        // Create averaged data with proper rounding
+        let avg_data = AverageAirQualityData {
+            temperature: temp_avg.map(|v| (v * 100.0).round() / 100.0), // 2 decimal places
+            humidity: humid_avg.map(|v| (v * 100.0).round() / 100.0),   // 2 decimal places
+            pressure: press_avg.map(|v| (v * 100.0).round() / 100.0),   // 2 decimal places
+            pm1_0: pm1_0_avg.map(|v| (v * 10.0).round() / 10.0),        // 1 decimal place
+            pm2_5: pm2_5_avg.map(|v| (v * 10.0).round() / 10.0),        // 1 decimal place
+            pm4_0: pm4_0_avg.map(|v| (v * 10.0).round() / 10.0),        // 1 decimal place
+            pm10_0: pm10_0_avg.map(|v| (v * 10.0).round() / 10.0),      // 1 decimal place
+            co2: co2_avg.map(|v| (v * 10.0).round() / 10.0),            // 1 decimal place
+            voc_index: voc_avg.map(|v| (v * 10.0).round() / 10.0),      // 1 decimal place
+            nox_index: nox_avg.map(|v| (v * 10.0).round() / 10.0),      // 1 decimal place
+            luminosity: lum_avg.map(|v| (v * 100.0).round() / 100.0),   // 2 decimal places
+            time: window.end,
+            name: config
+                .tags
+                .get(*sensor_id)
+                .cloned()
+                .unwrap_or_else(|| "Unknown".to_string()),
+            samples: data_points.len() as i32,
+            resolution: window.resolution as i32,
+            window_start: window.start,
+            partial: window.partial,
+            coverage: (coverage * 1000.0).round() / 1000.0, // 3 decimal places
+        };
+
+        averages.insert(sensor_id.to_string(), avg_data);
+    }
+
+    averages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag_reading(sequence: u16) -> SensorReading {
+        SensorReading::Tag(RuuviData {
+            temperature: Some(21.0),
+            humidity: None,
+            pressure: None,
+            acceleration_x: None,
+            acceleration_y: None,
+            acceleration_z: None,
+            movement_counter: None,
+            battery_voltage: None,
+            tx_power: None,
+            measurement_sequence: Some(sequence),
+            rssi: None,
+            adapter: None,
+            received_at: None,
+        })
+    }
+
+    fn air_reading(sequence: u32) -> SensorReading {
+        SensorReading::AirQuality(AirQualityData {
+            temperature: Some(21.0),
+            humidity: None,
+            pressure: None,
+            pm1_0: None,
+            pm2_5: None,
+            pm4_0: None,
+            pm10_0: None,
+            co2: None,
+            voc_index: None,
+            nox_index: None,
+            luminosity: None,
+            measurement_sequence: Some(sequence),
+            adapter: None,
+            received_at: None,
+        })
+    }
+
+    #[test]
+    fn sequence_tracker_drops_out_of_order_copies() {
+        let mut tracker = SequenceTracker::new();
+        assert!(tracker.is_new("tag", &tag_reading(100)));
+        assert!(tracker.is_new("tag", &tag_reading(101)));
+        // A second adapter relays the earlier measurement late
+        assert!(!tracker.is_new("tag", &tag_reading(100)));
+        assert!(!tracker.is_new("tag", &tag_reading(101)));
+        // Other sensors are tracked separately
+        assert!(tracker.is_new("other", &tag_reading(100)));
+    }
+
+    #[test]
+    fn sequence_tracker_forgets_old_sequences() {
+        let mut tracker = SequenceTracker::new();
+        for sequence in 0..=RECENT_SEQUENCES as u16 {
+            assert!(tracker.is_new("tag", &tag_reading(sequence)));
+        }
+        // The first sequence number has been pushed out, e.g. after a tag reboot
+        assert!(tracker.is_new("tag", &tag_reading(0)));
+    }
+
+    #[test]
+    fn sequence_tracker_matches_air_formats() {
+        let mut tracker = SequenceTracker::new();
+        // Format E1 carries 24 bits, format 6 the lowest 8 bits of the same counter
+        assert!(tracker.is_new("air", &air_reading(261)));
+        assert!(!tracker.is_new("air", &air_reading(5)));
+        assert!(tracker.is_new("air", &air_reading(262)));
+        assert!(!tracker.is_new("air", &air_reading(6)));
+        assert!(!tracker.is_new("air", &air_reading(261)));
+    }
+
+    fn tag_data(temperature: f32, acceleration_z: f32) -> RuuviData {
+        let SensorReading::Tag(data) = tag_reading(0) else {
+            unreachable!()
+        };
+        RuuviData {
+            temperature: Some(temperature),
+            acceleration_z: Some(acceleration_z),
+            ..data
+        }
+    }
+
+    #[test]
+    fn reject_outliers_removes_spikes_and_impossible_values() {
+        let mut readings: Vec<RuuviData> = (0..20)
+            .map(|index| tag_data(21.0 + index as f32 * 0.01, 1.0))
+            .collect();
+        readings[7].temperature = Some(2.0);
+        readings[12].acceleration_z = Some(-20.0);
+
+        let ([temperature, .., acceleration_z], rejected) = reject_outliers(&readings, 3.0);
+
+        assert_eq!(temperature[7], None);
+        assert_eq!(acceleration_z[12], None);
+        assert_eq!(temperature[12], readings[12].temperature);
+        assert_eq!(temperature.iter().filter(|v| v.is_none()).count(), 1);
+        assert_eq!(rejected.per_metric.get("temperature"), Some(&1));
+        assert_eq!(rejected.per_metric.get("acceleration_z"), Some(&1));
+        assert_eq!((rejected.atmospheric, rejected.movement), (1, 1));
+    }
+
+    fn seconds(offset: i64) -> Option<OffsetDateTime> {
+        Some(OffsetDateTime::from_unix_timestamp(1_704_110_400 + offset).unwrap())
+    }
+
+    #[test]
+    fn time_weighted_average_weights_readings_by_neighbour_gaps() {
+        // Sparse readings stand for 30 s on each side, the burst for 50 s in total
+        let samples = [
+            (0, 0.0),
+            (600, 0.0),
+            (1200, 0.0),
+            (1740, 10.0),
+            (1750, 10.0),
+            (1760, 10.0),
+        ];
+        let average = time_weighted_average(
+            samples
+                .iter()
+                .map(|&(time, value)| (seconds(time), Some(value))),
+        );
+        assert_eq!(average, Some(2.5));
+
+        // Evenly spread readings give the plain average
+        let samples = [(0, 1.0), (10, 2.0), (20, 3.0), (30, 4.0)];
+        let average = time_weighted_average(
+            samples
+                .iter()
+                .map(|&(time, value)| (seconds(time), Some(value))),
+        );
+        assert_eq!(average, Some(2.5));
+
+        // A single reading has no neighbours to weight it by
+        assert_eq!(
+            time_weighted_average([(seconds(0), Some(4.0))].into_iter()),
+            Some(4.0)
+        );
+        assert_eq!(
+            time_weighted_average([(seconds(0), None)].into_iter()),
+            None
+        );
+    }
+
+    #[test]
+    fn window_coverage_counts_only_time_within_the_window() {
+        let window = WindowBounds {
+            start: seconds(0).unwrap(),
+            end: seconds(600).unwrap(),
+            resolution: 600,
+            partial: false,
+        };
+
+        // Readings every 10 s from a minute before the window to a minute into it
+        let times = (-6..=6).map(|index| seconds(index * 10));
+        assert_eq!(window_coverage(times, &window), 0.1);
+
+        // Gaps longer than a minute are not covered
+        let times = [0, 30, 200, 230].into_iter().map(seconds);
+        assert_eq!(window_coverage(times, &window), 0.1);
+    }
+}
@@ -0,0 +1,431 @@
+/// Aggregation windows collecting readings from the shared advertisement stream
+use std::collections::HashMap;
+use time::{Duration, OffsetDateTime, UtcOffset};
+use time_tz::{Offset, TimeZone, Tz};
+
+use crate::ingest::Processed;
+use crate::models::{AirQualityData, DiscoveredTag, RuuviData, SensorReading, WindowBounds};
+
+/// Bounds and diagnostics of a single aggregation window
+///
+/// One window is kept per configured resolution. Every processed advertisement
+/// is counted in the windows its receive time falls into, and each window is
+/// aggregated from the shared `ReadingBuffer` and stored when its own deadline
+/// passes. Windows are aligned to wall-clock boundaries that are multiples of
+/// their length in the configured time zone, e.g. :00 and :30 for 30-minute
+/// windows or local midnight for 1-day windows.
+#[derive(Debug)]
+pub struct CollectionWindow {
+    /// Length of the window in seconds, stored as the resolution of its rows
+    pub resolution: u64,
+    /// Time zone of the local time the window boundaries are aligned to
+    pub time_zone: WindowTimeZone,
+    /// Aligned start of the window
+    pub start_time: OffsetDateTime,
+    pub deadline: OffsetDateTime,
+    /// Whether collection started after the start of the window
+    pub partial: bool,
+    /// Number of stale duplicate advertisements dropped per sensor
+    pub duplicates: HashMap<String, u32>,
+    /// Number of undecodable advertisements per sensor and error class
+    pub decode_errors: HashMap<String, HashMap<&'static str, u32>>,
+    /// Ruuvi devices that are not configured, reported in discovery mode
+    pub discovered: HashMap<String, DiscoveredTag>,
+}
+
+impl CollectionWindow {
+    /// Start an empty window containing the given time
+    ///
+    /// The window is flagged as partial unless the time is exactly on its boundary.
+    ///
+    /// # Arguments
+    /// * `resolution` - Length of the window in seconds
+    /// * `time_zone` - Time zone of the local time the boundaries are aligned to
+    /// * `time` - Time collection starts at
+    pub fn new(resolution: u64, time_zone: WindowTimeZone, time: OffsetDateTime) -> Self {
+        let (start_time, deadline) = align_to_window(time, resolution, time_zone);
+        CollectionWindow {
+            resolution,
+            time_zone,
+            start_time,
+            deadline,
+            partial: time > start_time,
+            duplicates: HashMap::new(),
+            decode_errors: HashMap::new(),
+            discovered: HashMap::new(),
+        }
+    }
+
+    /// Start the empty window that follows this one
+    ///
+    /// Collection continues without a break, so the next window is never
+    /// partial. Windows that passed without any processing (e.g. a gap in
+    /// replayed data) are skipped.
+    ///
+    /// # Arguments
+    /// * `now` - Current time, at or past the deadline of this window
+    pub fn next(&self, now: OffsetDateTime) -> Self {
+        CollectionWindow {
+            partial: false,
+            ..CollectionWindow::new(self.resolution, self.time_zone, now.max(self.deadline))
+        }
+    }
+
+    /// Bounds of the window for stamping its aggregates
+    ///
+    /// # Arguments
+    /// * `end_time` - Time collection ended at, before the deadline if the source was exhausted
+    pub fn bounds(&self, end_time: OffsetDateTime) -> WindowBounds {
+        WindowBounds {
+            start: self.start_time,
+            end: end_time.min(self.deadline),
+            resolution: self.resolution,
+            partial: self.partial || end_time < self.deadline,
+        }
+    }
+
+    /// Whether a receive time falls into the window
+    pub fn contains(&self, time: OffsetDateTime) -> bool {
+        self.start_time <= time && time < self.deadline
+    }
+
+    /// Count a processed advertisement in the window's diagnostics
+    ///
+    /// Readings themselves are kept once in the shared `ReadingBuffer`.
+    pub fn record(&mut self, processed: &Processed) {
+        match processed {
+            Processed::Reading(..) => {}
+            Processed::Duplicate(sensor_id) => {
+                *self.duplicates.entry(sensor_id.clone()).or_default() += 1;
+            }
+            // Count decode failures per error class to spot failing firmware or RF corruption
+            Processed::Failed(sensor_id, error) => {
+                *self
+                    .decode_errors
+                    .entry(sensor_id.clone())
+                    .or_default()
+                    .entry(error.kind())
+                    .or_default() += 1;
+            }
+            // Keep the latest advertisement of each unconfigured device
+            Processed::Discovered(tag) => {
+                let advertisements = self
+                    .discovered
+                    .get(&tag.mac)
+                    .map_or(0, |previous| previous.advertisements);
+                self.discovered.insert(
+                    tag.mac.clone(),
+                    DiscoveredTag {
+                        advertisements: advertisements + tag.advertisements,
+                        ..tag.clone()
+                    },
+                );
+            }
+            Processed::Unconfigured => {}
+        }
+    }
+}
+
+/// Time zone aggregation windows are aligned in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowTimeZone {
+    /// Fixed offset from UTC, without daylight saving time
+    Fixed(UtcOffset),
+    /// IANA time zone such as Europe/Helsinki, following daylight saving time
+    Named(&'static Tz),
+}
+
+impl WindowTimeZone {
+    /// UTC offset of the local time at a given time
+    pub fn offset_at(&self, time: OffsetDateTime) -> UtcOffset {
+        match self {
+            WindowTimeZone::Fixed(offset) => *offset,
+            WindowTimeZone::Named(time_zone) => time_zone.get_offset_utc(&time).to_utc(),
+        }
+    }
+
+    /// Convert a time to the local time, e.g. for logging
+    pub fn to_local(self, time: OffsetDateTime) -> OffsetDateTime {
+        time.to_offset(self.offset_at(time))
+    }
+}
+
+/// Readings shared by all aggregation windows
+///
+/// Every reading is kept once, in receive time order per sensor, and each
+/// window aggregates the slice received within its bounds. This keeps memory
+/// use to a single copy of the readings of the longest window no matter how
+/// many windows are configured.
+#[derive(Debug, Default)]
+pub struct ReadingBuffer {
+    /// Key: sensor MAC address, Value: readings from that sensor sorted by receive time
+    measurements: HashMap<String, Vec<RuuviData>>,
+    /// Ruuvi Air readings are kept separately as they carry different metrics
+    air_measurements: HashMap<String, Vec<AirQualityData>>,
+}
+
+impl ReadingBuffer {
+    /// Create an empty buffer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a reading, which must carry its receive time
+    ///
+    /// Readings relayed late (e.g. by a gateway) are inserted at their place
+    /// in time instead of at the end.
+    pub fn insert(&mut self, sensor_id: String, reading: SensorReading) {
+        match reading {
+            SensorReading::Tag(data) => {
+                insert_sorted(self.measurements.entry(sensor_id).or_default(), data, |d| {
+                    d.received_at
+                })
+            }
+            SensorReading::AirQuality(data) => insert_sorted(
+                self.air_measurements.entry(sensor_id).or_default(),
+                data,
+                |d| d.received_at,
+            ),
+        }
+    }
+
+    /// RuuviTag readings of each sensor received within a window
+    pub fn measurements(&self, window: &CollectionWindow) -> HashMap<&str, &[RuuviData]> {
+        readings_within(&self.measurements, window, |d| d.received_at)
+    }
+
+    /// Ruuvi Air readings of each sensor received within a window
+    pub fn air_measurements(&self, window: &CollectionWindow) -> HashMap<&str, &[AirQualityData]> {
+        readings_within(&self.air_measurements, window, |d| d.received_at)
+    }
+
+    /// Drop the readings received before a time, once no open window contains them
+    pub fn prune(&mut self, before: OffsetDateTime) {
+        prune_before(&mut self.measurements, before, |d| d.received_at);
+        prune_before(&mut self.air_measurements, before, |d| d.received_at);
+    }
+}
+
+/// Insert a reading into readings sorted by receive time
+fn insert_sorted<T>(readings: &mut Vec<T>, reading: T, time: fn(&T) -> Option<OffsetDateTime>) {
+    let received_at = time(&reading);
+    // Readings nearly always arrive in order, so search from the end
+    let position = readings
+        .iter()
+        .rposition(|r| time(r) <= received_at)
+        .map_or(0, |index| index + 1);
+    readings.insert(position, reading);
+}
+
+/// Slice the readings of each sensor down to those received within a window
+fn readings_within<'a, T>(
+    readings: &'a HashMap<String, Vec<T>>,
+    window: &CollectionWindow,
+    time: fn(&T) -> Option<OffsetDateTime>,
+) -> HashMap<&'a str, &'a [T]> {
+    readings
+        .iter()
+        .filter_map(|(sensor_id, readings)| {
+            let from = readings.partition_point(|r| time(r) < Some(window.start_time));
+            let to = readings.partition_point(|r| time(r) < Some(window.deadline));
+            (from < to).then(|| (sensor_id.as_str(), &readings[from..to]))
+        })
+        .collect()
+}
+
+/// Drop the readings received before a time, and sensors left without readings
+fn prune_before<T>(
+    readings: &mut HashMap<String, Vec<T>>,
+    before: OffsetDateTime,
+    time: fn(&T) -> Option<OffsetDateTime>,
+) {
+    readings.retain(|_, readings| {
+        let stale = readings.partition_point(|r| time(r) < Some(before));
+        readings.drain(..stale);
+        !readings.is_empty()
+    });
+}
+
+/// Find the start and end of the window of the given length containing a time
+///
+/// Boundaries are the times at which the local time is a multiple of the
+/// window length counted from the Unix epoch, so windows that divide a day
+/// start at local midnight. Windows spanning a daylight saving time change
+/// are an hour shorter or longer (a 1-day window lasts 23 or 25 hours), and a
+/// boundary skipped by the change to summer time moves forward by the skipped
+/// hour.
+fn align_to_window(
+    time: OffsetDateTime,
+    resolution: u64,
+    time_zone: WindowTimeZone,
+) -> (OffsetDateTime, OffsetDateTime) {
+    let length = resolution as i64;
+    let time = time.replace_nanosecond(0).unwrap_or(time);
+
+    // Offsets in effect around the time, two if daylight saving time changes nearby
+    let mut offsets: Vec<i64> = [
+        time - Duration::seconds(2 * length),
+        time,
+        time + Duration::seconds(2 * length),
+    ]
+    .iter()
+    .map(|t| time_zone.offset_at(*t).whole_seconds() as i64)
+    .collect();
+    offsets.sort();
+    offsets.dedup();
+
+    // Local boundaries before and after the time under each of the offsets
+    let mut local_boundaries: Vec<i64> = offsets
+        .iter()
+        .flat_map(|offset| {
+            let local_seconds = time.unix_timestamp() + offset;
+            let window_start = local_seconds - local_seconds.rem_euclid(length);
+            (-1..=2).map(move |n| window_start + n * length)
+        })
+        .collect();
+    local_boundaries.sort();
+    local_boundaries.dedup();
+
+    // Times at which the local clock shows a boundary, twice for a boundary
+    // repeated by the change back to winter time
+    let mut boundaries: Vec<i64> = Vec::new();
+    for local_seconds in local_boundaries {
+        let matching: Vec<i64> = offsets
+            .iter()
+            .map(|offset| local_seconds - offset)
+            .filter(|&seconds| {
+                OffsetDateTime::from_unix_timestamp(seconds).is_ok_and(|t| {
+                    time_zone.offset_at(t).whole_seconds() as i64 == local_seconds - seconds
+                })
+            })
+            .collect();
+        if matching.is_empty() {
+            // Skipped by the change to summer time, offsets[0] being the winter offset
+            boundaries.push(local_seconds - offsets[0]);
+        } else {
+            boundaries.extend(matching);
+        }
+    }
+    boundaries.sort();
+
+    let now = time.unix_timestamp();
+    let start = boundaries
+        .iter()
+        .rev()
+        .copied()
+        .find(|&seconds| seconds <= now)
+        .unwrap_or(now);
+    let end = boundaries
+        .iter()
+        .copied()
+        .find(|&seconds| seconds > start)
+        .unwrap_or(start + length);
+    (
+        time + Duration::seconds(start - now),
+        time + Duration::seconds(end - now),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading_at(time: OffsetDateTime, temperature: f32) -> SensorReading {
+        SensorReading::Tag(RuuviData {
+            temperature: Some(temperature),
+            humidity: None,
+            pressure: None,
+            acceleration_x: None,
+            acceleration_y: None,
+            acceleration_z: None,
+            movement_counter: None,
+            battery_voltage: None,
+            tx_power: None,
+            measurement_sequence: None,
+            rssi: None,
+            adapter: None,
+            received_at: Some(time),
+        })
+    }
+
+    #[test]
+    fn reading_buffer_slices_windows_by_receive_time() {
+        let hour = OffsetDateTime::from_unix_timestamp(1_704_110_400).unwrap(); // 2024-01-01 12:00 UTC
+        let at = |seconds: i64| hour + Duration::seconds(seconds);
+        let utc = WindowTimeZone::Fixed(UtcOffset::UTC);
+        let half_hour = CollectionWindow::new(1800, utc, at(1800));
+        let full_hour = CollectionWindow::new(3600, utc, at(1800));
+
+        let mut buffer = ReadingBuffer::new();
+        for (seconds, temperature) in [(1790, 1.0), (1810, 3.0), (1800, 2.0), (3600, 4.0)] {
+            buffer.insert("tag".to_string(), reading_at(at(seconds), temperature));
+        }
+
+        let temperatures = |window: &CollectionWindow, buffer: &ReadingBuffer| -> Vec<f32> {
+            buffer.measurements(window)["tag"]
+                .iter()
+                .filter_map(|d| d.temperature)
+                .collect()
+        };
+        assert_eq!(temperatures(&half_hour, &buffer), vec![2.0, 3.0]);
+        assert_eq!(temperatures(&full_hour, &buffer), vec![1.0, 2.0, 3.0]);
+
+        buffer.prune(at(1800));
+        assert_eq!(temperatures(&full_hour, &buffer), vec![2.0, 3.0]);
+        buffer.prune(at(3601));
+        assert!(buffer.measurements(&half_hour.next(at(3600))).is_empty());
+    }
+
+    /// Start and length in hours of the window containing a time
+    fn window_hours(resolution: u64, time_zone: WindowTimeZone, unix: i64) -> (i64, i64) {
+        let time = OffsetDateTime::from_unix_timestamp(unix).unwrap();
+        let window = CollectionWindow::new(resolution, time_zone, time);
+        (
+            window.start_time.unix_timestamp(),
+            (window.deadline - window.start_time).whole_hours(),
+        )
+    }
+
+    #[test]
+    fn windows_follow_daylight_saving_time() {
+        let helsinki =
+            WindowTimeZone::Named(time_tz::timezones::get_by_name("Europe/Helsinki").unwrap());
+
+        // Clocks go forward from 03:00 to 04:00 on 2024-03-31 and back on 2024-10-27
+        assert_eq!(
+            window_hours(86400, helsinki, 1_711_886_400), // 2024-03-31 12:00 UTC
+            (1_711_836_000, 23)                           // from 2024-03-31 00:00 +02:00
+        );
+        assert_eq!(
+            window_hours(86400, helsinki, 1_730_030_400), // 2024-10-27 12:00 UTC
+            (1_729_976_400, 25)                           // from 2024-10-27 00:00 +03:00
+        );
+        assert_eq!(
+            window_hours(86400, helsinki, 1_730_116_800), // 2024-10-28 12:00 UTC
+            (1_730_066_400, 24)                           // from 2024-10-28 00:00 +02:00
+        );
+
+        // The window from 02:30 ends at 03:00 EET, which is 04:00 EEST
+        let time = OffsetDateTime::from_unix_timestamp(1_711_845_600).unwrap(); // 00:40 UTC
+        let window = CollectionWindow::new(1800, helsinki, time);
+        assert_eq!(window.deadline.unix_timestamp(), 1_711_846_800);
+        assert_eq!(helsinki.to_local(window.deadline).hour(), 4);
+        let next = window.next(window.deadline);
+        assert_eq!(next.deadline - next.start_time, Duration::minutes(30));
+
+        // Hourly windows continue through the repeated hour
+        assert_eq!(
+            window_hours(3600, helsinki, 1_729_990_800), // 2024-10-27 01:00 UTC
+            (1_729_990_800, 1)                           // from the second 03:00
+        );
+    }
+
+    #[test]
+    fn windows_align_to_fixed_offset() {
+        let offset = WindowTimeZone::Fixed(UtcOffset::from_hms(5, 30, 0).unwrap());
+        assert_eq!(
+            window_hours(86400, offset, 1_704_110_400), // 2024-01-01 12:00 UTC
+            (1_704_047_400, 24)                         // from 2024-01-01 00:00 +05:30
+        );
+    }
+}